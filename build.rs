@@ -1,16 +1,29 @@
+use std::path::PathBuf;
+
 const SERIAL_DESERIAL_ATTR: &str = "#[derive(serde::Serialize, serde::Deserialize)]";
+// used for the OpenAPI document of the REST gateway
+const JSON_SCHEMA_ATTR: &str = "#[derive(schemars::JsonSchema)]";
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+
     tonic_build::configure()
         .build_server(true)
         .build_client(true)
         .protoc_arg("--experimental_allow_proto3_optional")
+        .compile_well_known_types(true)
         .type_attribute(".", SERIAL_DESERIAL_ATTR)
+        .type_attribute(".", JSON_SCHEMA_ATTR)
         .include_file("proto.rs")
+        // used by the reflection service
+        .file_descriptor_set_path(out_dir.join("timebank_descriptor.bin"))
         .compile(
             &[
+                "proto/admin.proto",
                 "proto/auth.proto",
+                "proto/moderation.proto",
                 "proto/user.proto",
+                "proto/organisation.proto",
                 "proto/collection/rating.proto",
                 "proto/collection/service-request.proto",
             ],
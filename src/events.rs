@@ -0,0 +1,46 @@
+//! In-process events about service requests. Anything in the server can
+//! subscribe to them, e.g. to send notifications.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::info;
+
+const CHANNEL_CAPACITY: usize = 256;
+
+static BUS: Lazy<broadcast::Sender<ServiceRequestEvent>> =
+    Lazy::new(|| broadcast::channel(CHANNEL_CAPACITY).0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Expired,
+    ApplicationRejected,
+    NoShow,
+}
+
+/// A change to a service request that was not made by one of its users.
+/// `user_id` is the user affected by the change, if any.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceRequestEvent {
+    pub request_id: String,
+    pub kind: EventKind,
+    pub from_state: Option<i32>,
+    pub to_state: Option<i32>,
+    pub user_id: Option<String>,
+}
+
+pub fn publish(event: ServiceRequestEvent) {
+    info!(
+        "event kind={:?} request_id={} user_id={:?}",
+        event.kind, event.request_id, event.user_id
+    );
+
+    // there being no subscribers is not an error
+    let _ = BUS.send(event);
+}
+
+#[allow(unused)]
+pub fn subscribe() -> broadcast::Receiver<ServiceRequestEvent> {
+    BUS.subscribe()
+}
@@ -0,0 +1,544 @@
+//! A REST/JSON gateway over the gRPC services, served at `GATEWAY_ADDRESS`
+//! when it is set. Every route calls the same service implementation as the
+//! gRPC server, and goes through the same authentication, policies, rate
+//! limits and idempotency keys. It is served over TLS when the gRPC server is,
+//! with the same certificates. The OpenAPI document of the routes is served on
+//! `/openapi.json`.
+
+mod openapi;
+
+use std::{collections::HashMap, future::Future, net::SocketAddr, sync::Arc, time::Instant};
+
+use axum::{
+    body::Bytes,
+    extract::{connect_info::Connected, ConnectInfo, Path, Query},
+    http::{header::HeaderName, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, on, MethodFilter},
+    Json, Router, Server,
+};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tonic::{Code, Status};
+use tracing::{field, info, info_span, warn, Instrument, Span};
+use uuid::Uuid;
+
+use self::openapi::Document;
+use crate::layers::{
+    self,
+    auth::{bearer_token, AuthenticatedUser},
+    idempotency::{IdempotencyStore, IDEMPOTENCY_KEY_HEADER},
+    logger, policy,
+    rate_limit::{self, IpRateLimiter, RateLimiter},
+};
+use crate::metrics::{RPC_DURATION, RPC_REQUESTS};
+use crate::proto::{
+    admin::admin_server::Admin as _, auth::auth_server::Auth as _,
+    organisation::organisation_server::Organisation as _, rating::rating_server::Rating as _,
+    servicerequest::service_request_server::ServiceRequest as _, user::user_server::User as _,
+};
+use crate::services::{
+    admin::AdminService, auth::AuthService, organisation::OrganisationService,
+    rating::RatingService, service_request::ServiceRequestService, user::UserService,
+};
+use crate::supabase::auth::AuthClient;
+use crate::tls;
+
+/// The service implementations shared with the gRPC server.
+pub struct Services {
+    pub service_requests: Arc<ServiceRequestService>,
+    pub ratings: Arc<RatingService>,
+    pub users: Arc<UserService>,
+    pub auth: Arc<AuthService>,
+    pub organisations: Arc<OrganisationService>,
+    pub admin: Arc<AdminService>,
+}
+
+pub fn start(
+    services: Services,
+    ip_limiter: IpRateLimiter,
+    limiter: Arc<RateLimiter>,
+    idempotency: Arc<IdempotencyStore>,
+) {
+    let addr = match dotenv::var("GATEWAY_ADDRESS") {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+
+    let addr: SocketAddr = addr
+        .parse()
+        .expect("UNABLE TO PARSE GATEWAY ADDRESS STRING");
+
+    let gateway = Arc::new(Gateway {
+        auth: AuthClient::new(),
+        ip_limiter,
+        limiter,
+        idempotency,
+    });
+
+    let router = routes(Routes::new(gateway), &services).into_router();
+
+    let service = router.into_make_service_with_connect_info::<Peer>();
+
+    tokio::spawn(async move {
+        let res = match tls::TlsConfig::from_env() {
+            Some(config) => {
+                let incoming = match tls::incoming(addr, config).await {
+                    Ok(incoming) => incoming,
+                    Err(e) => {
+                        warn!("unable to serve REST gateway over TLS error={e}");
+                        return;
+                    }
+                };
+
+                info!("Serving REST gateway on {} over TLS", addr);
+                Server::builder(hyper::server::accept::from_stream(incoming))
+                    .serve(service)
+                    .await
+            }
+            None => {
+                info!("Serving REST gateway on {}", addr);
+                Server::bind(&addr).serve(service).await
+            }
+        };
+
+        if let Err(e) = res {
+            warn!("REST gateway stopped error={e}");
+        }
+    });
+}
+
+/// Calls `method` of a shared service, e.g. `rpc!(users, get_profile)`.
+macro_rules! rpc {
+    ($service:expr, $method:ident) => {{
+        let service = Arc::clone($service);
+
+        move |request| {
+            let service = service.clone();
+            async move { service.$method(request).await }
+        }
+    }};
+}
+
+// Path parameters are named after the field of the request message they set.
+// `get` and the streaming `ExportTransactionHistory` have no route.
+#[rustfmt::skip]
+fn routes(routes: Routes, services: &Services) -> Routes {
+    let Services {
+        service_requests,
+        ratings,
+        users,
+        auth,
+        organisations,
+        admin,
+    } = services;
+
+    routes
+        .add(Verb::Post, "/v1/auth/sign-up", "/auth.Auth/SignUp", rpc!(auth, sign_up))
+        .add(Verb::Post, "/v1/service-requests", "/servicerequest.ServiceRequest/Create", rpc!(service_requests, create))
+        .add(Verb::Get, "/v1/service-requests/available", "/servicerequest.ServiceRequest/GetAvailable", rpc!(service_requests, get_available))
+        .add(Verb::Get, "/v1/service-requests/:request_id", "/servicerequest.ServiceRequest/GetById", rpc!(service_requests, get_by_id))
+        .add(Verb::Patch, "/v1/service-requests/:request_id", "/servicerequest.ServiceRequest/Update", rpc!(service_requests, update))
+        .add(Verb::Delete, "/v1/service-requests/:request_id", "/servicerequest.ServiceRequest/Delete", rpc!(service_requests, delete))
+        .add(Verb::Post, "/v1/service-requests/:request_id/applications", "/servicerequest.ServiceRequest/ApplyProvider", rpc!(service_requests, apply_provider))
+        .add(Verb::Delete, "/v1/service-requests/:request_id/applications/:provider", "/servicerequest.ServiceRequest/WithdrawApplication", rpc!(service_requests, withdraw_application))
+        .add(Verb::Post, "/v1/service-requests/:request_id/provider", "/servicerequest.ServiceRequest/SelectProvider", rpc!(service_requests, select_provider))
+        .add(Verb::Post, "/v1/service-requests/:request_id/start", "/servicerequest.ServiceRequest/StartService", rpc!(service_requests, start_service))
+        .add(Verb::Post, "/v1/service-requests/:request_id/complete", "/servicerequest.ServiceRequest/CompleteService", rpc!(service_requests, complete_service))
+        .add(Verb::Post, "/v1/service-requests/:request_id/cancel", "/servicerequest.ServiceRequest/Cancel", rpc!(service_requests, cancel))
+        .add(Verb::Post, "/v1/service-requests/:request_id/disputes", "/servicerequest.ServiceRequest/OpenDispute", rpc!(service_requests, open_dispute))
+        .add(Verb::Post, "/v1/service-requests/:request_id/timer/start", "/servicerequest.ServiceRequest/StartTimer", rpc!(service_requests, start_timer))
+        .add(Verb::Post, "/v1/service-requests/:request_id/timer/stop", "/servicerequest.ServiceRequest/StopTimer", rpc!(service_requests, stop_timer))
+        .add(Verb::Post, "/v1/service-requests/:request_id/time-entries", "/servicerequest.ServiceRequest/AddTimeEntry", rpc!(service_requests, add_time_entry))
+        .add(Verb::Get, "/v1/service-requests/:request_id/time-log", "/servicerequest.ServiceRequest/GetTimeLog", rpc!(service_requests, get_time_log))
+        .add(Verb::Post, "/v1/service-requests/:request_id/time-log/confirm", "/servicerequest.ServiceRequest/ConfirmTimeLog", rpc!(service_requests, confirm_time_log))
+        .add(Verb::Post, "/v1/disputes/:dispute_id/resolve", "/servicerequest.ServiceRequest/ResolveDispute", rpc!(service_requests, resolve_dispute))
+        .add(Verb::Put, "/v1/series/:series_id", "/servicerequest.ServiceRequest/UpdateSeries", rpc!(service_requests, update_series))
+        .add(Verb::Delete, "/v1/series/:series_id", "/servicerequest.ServiceRequest/CancelSeries", rpc!(service_requests, cancel_series))
+        .add(Verb::Post, "/v1/ratings/provider", "/rating.Rating/CreateForProvider", rpc!(ratings, create_for_provider))
+        .add(Verb::Post, "/v1/ratings/requestor", "/rating.Rating/CreateForRequestor", rpc!(ratings, create_for_requestor))
+        .add(Verb::Get, "/v1/service-requests/:request_id/ratings", "/rating.Rating/GetForRequest", rpc!(ratings, get_for_request))
+        .add(Verb::Get, "/v1/service-requests/:request_id/ratings/:rating_for", "/rating.Rating/GetById", rpc!(ratings, get_by_id))
+        .add(Verb::Patch, "/v1/service-requests/:request_id/ratings/:rating_for", "/rating.Rating/Update", rpc!(ratings, update))
+        .add(Verb::Delete, "/v1/service-requests/:request_id/ratings/:rating_for", "/rating.Rating/Delete", rpc!(ratings, delete))
+        .add(Verb::Get, "/v1/users/:user_id", "/user.User/GetById", rpc!(users, get_by_id))
+        .add(Verb::Patch, "/v1/users/:user_id", "/user.User/Update", rpc!(users, update))
+        .add(Verb::Get, "/v1/users/:user_id/profile", "/user.User/GetProfile", rpc!(users, get_profile))
+        .add(Verb::Get, "/v1/users/:user_id/credit-balance", "/user.User/GetCreditBalance", rpc!(users, get_credit_balance))
+        .add(Verb::Get, "/v1/users/:user_id/transactions", "/user.User/GetTransactionHistory", rpc!(users, get_transaction_history))
+        .add(Verb::Get, "/v1/users/:user_id/service-requests/summary", "/servicerequest.ServiceRequest/GetSummaryForUser", rpc!(service_requests, get_summary_for_user))
+        .add(Verb::Post, "/v1/transfers", "/user.User/TransferCredits", rpc!(users, transfer_credits))
+        .add(Verb::Post, "/v1/reports", "/user.User/Report", rpc!(users, report))
+        .add(Verb::Post, "/v1/organisations", "/organisation.Organisation/Create", rpc!(organisations, create))
+        .add(Verb::Get, "/v1/organisations/:organisation_id", "/organisation.Organisation/GetById", rpc!(organisations, get_by_id))
+        .add(Verb::Get, "/v1/organisations/:organisation_id/credit-balance", "/organisation.Organisation/GetCreditBalance", rpc!(organisations, get_credit_balance))
+        .add(Verb::Get, "/v1/organisations/:organisation_id/members", "/organisation.Organisation/GetMembers", rpc!(organisations, get_members))
+        .add(Verb::Post, "/v1/organisations/:organisation_id/members", "/organisation.Organisation/AddMember", rpc!(organisations, add_member))
+        .add(Verb::Patch, "/v1/organisations/:organisation_id/members/:user_id", "/organisation.Organisation/UpdateMemberRole", rpc!(organisations, update_member_role))
+        .add(Verb::Delete, "/v1/organisations/:organisation_id/members/:user_id", "/organisation.Organisation/RemoveMember", rpc!(organisations, remove_member))
+        .add(Verb::Post, "/v1/admin/users/:user_id/suspend", "/admin.Admin/SuspendUser", rpc!(admin, suspend_user))
+        .add(Verb::Post, "/v1/admin/users/:user_id/unsuspend", "/admin.Admin/UnsuspendUser", rpc!(admin, unsuspend_user))
+        .add(Verb::Post, "/v1/admin/users/:user_id/credits", "/admin.Admin/AdjustCredits", rpc!(admin, adjust_credits))
+        .add(Verb::Post, "/v1/admin/service-requests/:request_id/cancel", "/admin.Admin/ForceCancel", rpc!(admin, force_cancel))
+        .add(Verb::Get, "/v1/admin/flagged", "/admin.Admin/ListFlagged", rpc!(admin, list_flagged))
+        .add(Verb::Get, "/v1/admin/reports", "/admin.Admin/GetModerationQueue", rpc!(admin, get_moderation_queue))
+        .add(Verb::Post, "/v1/admin/reports/:report_id/resolve", "/admin.Admin/ResolveReport", rpc!(admin, resolve_report))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Verb {
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+impl Verb {
+    fn filter(self) -> MethodFilter {
+        match self {
+            Self::Get => MethodFilter::GET,
+            Self::Post => MethodFilter::POST,
+            Self::Put => MethodFilter::PUT,
+            Self::Patch => MethodFilter::PATCH,
+            Self::Delete => MethodFilter::DELETE,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Get => "get",
+            Self::Post => "post",
+            Self::Put => "put",
+            Self::Patch => "patch",
+            Self::Delete => "delete",
+        }
+    }
+
+    /// Whether the request message is sent as a JSON body rather than as
+    /// query parameters.
+    fn has_body(self) -> bool {
+        !matches!(self, Self::Get | Self::Delete)
+    }
+}
+
+struct Gateway {
+    auth: AuthClient,
+    ip_limiter: IpRateLimiter,
+    limiter: Arc<RateLimiter>,
+    idempotency: Arc<IdempotencyStore>,
+}
+
+/// The address of the client, for connections over TLS or not.
+#[derive(Clone, Copy)]
+struct Peer(Option<SocketAddr>);
+
+impl Connected<&hyper::server::conn::AddrStream> for Peer {
+    fn connect_info(target: &hyper::server::conn::AddrStream) -> Self {
+        Self(Some(target.remote_addr()))
+    }
+}
+
+impl Connected<&TlsStream<TcpStream>> for Peer {
+    fn connect_info(target: &TlsStream<TcpStream>) -> Self {
+        Self(target.get_ref().0.peer_addr().ok())
+    }
+}
+
+/// What a route receives besides the body.
+struct Input {
+    headers: HeaderMap,
+    params: HashMap<String, String>,
+    query: HashMap<String, String>,
+    addr: Option<SocketAddr>,
+}
+
+impl Gateway {
+    /// Runs a call in its own span, tagged like the ones of `RequestLogger`,
+    /// and records it in the RPC metrics.
+    async fn handle<Req, Res, F, Fut>(
+        self: Arc<Self>,
+        rpc: &'static str,
+        input: Input,
+        body: Bytes,
+        call: F,
+    ) -> Response
+    where
+        Req: Default + Serialize + DeserializeOwned,
+        Res: Serialize,
+        F: FnOnce(tonic::Request<Req>) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<Res>, Status>>,
+    {
+        let request_id = input
+            .headers
+            .get(logger::REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let span = info_span!(
+            "rest",
+            method = rpc,
+            request_id = request_id.as_str(),
+            peer = input.addr.map(|addr| addr.to_string()).as_deref(),
+            user_id = field::Empty,
+            grpc_status = field::Empty,
+            duration_ms = field::Empty,
+        );
+
+        let response_id = request_id.clone();
+
+        let fut = async move {
+            let start = Instant::now();
+            let res = self.dispatch(rpc, input, body, call).await;
+            let duration_ms = start.elapsed().as_millis() as u64;
+
+            let code = match &res {
+                Ok(_) => Code::Ok,
+                Err(status) => status.code(),
+            };
+            let grpc_status = (code as i32).to_string();
+
+            RPC_DURATION
+                .with_label_values(&[rpc])
+                .observe(start.elapsed().as_secs_f64());
+            RPC_REQUESTS.with_label_values(&[rpc, &grpc_status]).inc();
+
+            let span = Span::current();
+            span.record("duration_ms", duration_ms);
+            span.record("grpc_status", grpc_status.as_str());
+            info!(%grpc_status, duration_ms, "finished");
+
+            let mut res = match res {
+                Ok(value) => Json(value).into_response(),
+                Err(status) => error_response(status),
+            };
+
+            if let Ok(value) = HeaderValue::from_str(&response_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(logger::REQUEST_ID_HEADER), value);
+            }
+
+            res
+        };
+
+        logger::scope_request_id(request_id, fut.instrument(span)).await
+    }
+
+    async fn dispatch<Req, Res, F, Fut>(
+        &self,
+        rpc: &'static str,
+        input: Input,
+        body: Bytes,
+        call: F,
+    ) -> Result<Value, Status>
+    where
+        Req: Default + Serialize + DeserializeOwned,
+        Res: Serialize,
+        F: FnOnce(tonic::Request<Req>) -> Fut,
+        Fut: Future<Output = Result<tonic::Response<Res>, Status>>,
+    {
+        let ip = layers::client_ip_from(&input.headers, input.addr);
+
+        self.ip_limiter.check(ip)?;
+
+        let user = match bearer_token(&input.headers) {
+            Some(token) => Some(AuthenticatedUser::from_token(&self.auth, token).await?),
+            None => None,
+        };
+
+        policy::check(rpc, user.as_ref())?;
+
+        let client = rate_limit::client_key(user.as_ref(), ip);
+
+        self.limiter.check(&client, rpc)?;
+
+        let message = decode::<Req>(input.params, input.query, &body)?;
+
+        let key = input
+            .headers
+            .get(IDEMPOTENCY_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(|key| format!("rest:{client}:{rpc}:{key}"));
+
+        // The fingerprint is taken from the decoded message, so that the same
+        // request is recognised whether its fields came from the body, the
+        // path or the query.
+        let payload = match &key {
+            Some(_) => serde_json::to_vec(&message).map_err(|e| Status::internal(e.to_string()))?,
+            None => Vec::new(),
+        };
+
+        let mut request = tonic::Request::new(message);
+
+        if let Some(user) = user {
+            request.extensions_mut().insert(user);
+        }
+
+        let run = async move {
+            let res = call(request).await?.into_inner();
+
+            serde_json::to_vec(&res)
+                .map(Bytes::from)
+                .map_err(|e| Status::internal(e.to_string()))
+        };
+
+        let res = match key {
+            Some(key) => self.idempotency.call(key, &payload, run).await?,
+            None => run.await?,
+        };
+
+        serde_json::from_slice(&res).map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+/// Builds the request message from the JSON body, then the query and path
+/// parameters, in increasing order of precedence. Fields that aren't set keep
+/// their default value, like in protobuf. Parameters are parsed according to
+/// the type of the field they set.
+fn decode<T>(
+    params: HashMap<String, String>,
+    query: HashMap<String, String>,
+    body: &[u8],
+) -> Result<T, Status>
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    let mut fields = match serde_json::to_value(T::default()) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+
+    if !body.is_empty() {
+        match serde_json::from_slice(body) {
+            Ok(Value::Object(body)) => fields.extend(body),
+            Ok(_) => return Err(Status::invalid_argument("expected a JSON object")),
+            Err(e) => return Err(Status::invalid_argument(format!("invalid JSON body : {e}"))),
+        }
+    }
+
+    for (key, value) in query.into_iter().chain(params) {
+        let value = match fields.get(&key) {
+            Some(Value::Number(_) | Value::Bool(_) | Value::Object(_) | Value::Array(_)) => {
+                serde_json::from_str(&value)
+                    .map_err(|_| Status::invalid_argument(format!("invalid value for `{key}`")))?
+            }
+            _ => Value::String(value),
+        };
+
+        fields.insert(key, value);
+    }
+
+    serde_json::from_value(Value::Object(fields))
+        .map_err(|e| Status::invalid_argument(format!("invalid request : {e}")))
+}
+
+/// Same mapping as grpc-gateway, with the status in the body.
+fn error_response(status: Status) -> Response {
+    let code = match status.code() {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).unwrap(),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    let body = json!({
+        "code": status.code() as i32,
+        "message": status.message(),
+    });
+
+    let mut res = (code, Json(body)).into_response();
+
+    if let Some(value) = status
+        .metadata()
+        .get("retry-after")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        res.headers_mut()
+            .insert(axum::http::header::RETRY_AFTER, value);
+    }
+
+    res
+}
+
+struct Routes {
+    router: Router,
+    document: Document,
+    gateway: Arc<Gateway>,
+}
+
+impl Routes {
+    fn new(gateway: Arc<Gateway>) -> Self {
+        Self {
+            router: Router::new(),
+            document: Document::new(),
+            gateway,
+        }
+    }
+
+    /// Routes `verb path` to the RPC at `rpc`, which is also the method
+    /// policies and rate limits are looked up with.
+    fn add<Req, Res, F, Fut>(
+        mut self,
+        verb: Verb,
+        path: &'static str,
+        rpc: &'static str,
+        call: F,
+    ) -> Self
+    where
+        Req: Default + Serialize + DeserializeOwned + JsonSchema + Send + 'static,
+        Res: Serialize + JsonSchema + Send + 'static,
+        F: Fn(tonic::Request<Req>) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = Result<tonic::Response<Res>, Status>> + Send + 'static,
+    {
+        self.document.add::<Req, Res>(verb, path, rpc);
+
+        let gateway = self.gateway.clone();
+
+        let handler = move |headers: HeaderMap,
+                            params: Option<Path<HashMap<String, String>>>,
+                            Query(query): Query<HashMap<String, String>>,
+                            peer: Option<ConnectInfo<Peer>>,
+                            body: Bytes| {
+            let gateway = gateway.clone();
+            let call = call.clone();
+
+            let input = Input {
+                headers,
+                params: params.map(|Path(params)| params).unwrap_or_default(),
+                query,
+                addr: peer.and_then(|ConnectInfo(Peer(addr))| addr),
+            };
+
+            async move { gateway.handle(rpc, input, body, call).await }
+        };
+
+        self.router = self.router.route(path, on(verb.filter(), handler));
+        self
+    }
+
+    fn into_router(self) -> Router {
+        let document = Arc::new(self.document.into_json());
+
+        self.router.route(
+            "/openapi.json",
+            get(move || async move { Json(document.as_ref().clone()) }),
+        )
+    }
+}
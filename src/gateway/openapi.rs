@@ -0,0 +1,156 @@
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde_json::{json, Map, Value};
+
+use super::Verb;
+use crate::layers::policy;
+
+/// The OpenAPI 3.0 document of the gateway, with the schemas of the request
+/// and response messages derived from the generated proto types.
+pub struct Document {
+    paths: Map<String, Value>,
+    schemas: Map<String, Value>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self {
+            paths: Map::new(),
+            schemas: Map::new(),
+        }
+    }
+
+    pub fn add<Req, Res>(&mut self, verb: Verb, path: &str, rpc: &str)
+    where
+        Req: JsonSchema,
+        Res: JsonSchema,
+    {
+        // e.g. `user.User.GetProfile`
+        let id = rpc.trim_start_matches('/').replace('/', ".");
+
+        let request = self.schema_for::<Req>(&format!("{id}.Request"));
+        let response = self.schema_for::<Res>(&format!("{id}.Response"));
+
+        let path_params: Vec<&str> = path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix(':'))
+            .collect();
+
+        let mut parameters: Vec<Value> = path_params
+            .iter()
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                })
+            })
+            .collect();
+
+        let mut operation = json!({
+            "operationId": id,
+            "summary": format!("Calls `{rpc}`"),
+            "responses": {
+                "200": {
+                    "description": "OK",
+                    "content": { "application/json": { "schema": response } },
+                },
+                "default": {
+                    "description": "The status of the failed RPC",
+                    "content": { "application/json": { "schema": error_schema() } },
+                },
+            },
+        });
+
+        if verb.has_body() {
+            operation["requestBody"] = json!({
+                "content": { "application/json": { "schema": request } },
+            });
+        } else if let Some(properties) = request["properties"].as_object() {
+            parameters.extend(
+                properties
+                    .iter()
+                    .filter(|(name, _)| !path_params.contains(&name.as_str()))
+                    .map(|(name, schema)| {
+                        json!({
+                            "name": name,
+                            "in": "query",
+                            "schema": schema,
+                        })
+                    }),
+            );
+        }
+
+        operation["parameters"] = Value::Array(parameters);
+
+        // methods without a policy can be called anonymously
+        operation["security"] = if policy::check(rpc, None).is_ok() {
+            json!([{}, { "bearerAuth": [] }])
+        } else {
+            json!([{ "bearerAuth": [] }])
+        };
+
+        let path = path
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => format!("{{{name}}}"),
+                None => segment.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let item = self
+            .paths
+            .entry(path)
+            .or_insert_with(|| Value::Object(Map::new()));
+
+        item[verb.as_str()] = operation;
+    }
+
+    // The generated types share names across packages (every RPC has its own
+    // `Request` and `Response`), so the definitions of each schema are stored
+    // under the schema's own prefix.
+    fn schema_for<T: JsonSchema>(&mut self, prefix: &str) -> Value {
+        let settings = SchemaSettings::openapi3().with(|s| {
+            s.definitions_path = format!("#/components/schemas/{prefix}.");
+        });
+
+        let root = settings.into_generator().into_root_schema_for::<T>();
+
+        for (name, schema) in root.definitions {
+            self.schemas.insert(
+                format!("{prefix}.{name}"),
+                serde_json::to_value(schema).unwrap_or_default(),
+            );
+        }
+
+        serde_json::to_value(root.schema).unwrap_or_default()
+    }
+
+    pub fn into_json(self) -> Value {
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "Timebank REST gateway",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": self.schemas,
+                "securitySchemes": {
+                    "bearerAuth": { "type": "http", "scheme": "bearer" },
+                },
+            },
+        })
+    }
+}
+
+fn error_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "code": { "type": "integer", "description": "gRPC status code" },
+            "message": { "type": "string" },
+        },
+    })
+}
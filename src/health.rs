@@ -0,0 +1,122 @@
+//! Readiness checks behind the `grpc.health.v1.Health` service.
+//!
+//! The server (`""`) and every gRPC service are serving when the config is
+//! complete and Supabase can be reached. StarkNet is only used for
+//! commitments, and a commitment that can't be submitted is logged without
+//! failing the RPC, so an unreachable gateway only marks the `starknet`
+//! component as not serving. Those commitments are not resubmitted.
+
+use std::time::Duration;
+
+use starknet::{core::types::BlockId, providers::Provider};
+use tonic_health::{server::HealthReporter, ServingStatus};
+use tracing::{info, warn};
+
+use crate::starknet::provider::StarkNetProvider;
+use crate::supabase;
+
+const DEFAULT_INTERVAL_SECS: u64 = 15;
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const REQUIRED_CONFIG: &[&str] = &[
+    "SUPABASE_ENDPOINT",
+    "SUPABASE_API_KEY",
+    "SUPABASE_AUTH_ENDPOINT",
+    "STARKNET_GATEWAY_URL",
+    "STARKNET_FEEDER_GATEWAY_URL",
+    "ADMIN_PRIVATE_KEY",
+    "ADMIN_ACCOUNT_ADDRESS",
+    "BUDI_CORE_CONTRACT_ADDRESS",
+];
+
+const STARKNET_COMPONENT: &str = "starknet";
+
+fn check_config() -> Result<(), String> {
+    let missing: Vec<&str> = REQUIRED_CONFIG
+        .iter()
+        .copied()
+        .filter(|key| dotenv::var(key).map_or(true, |v| v.is_empty()))
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("missing config : {}", missing.join(", ")))
+    }
+}
+
+async fn check_supabase() -> Result<(), String> {
+    match tokio::time::timeout(CHECK_TIMEOUT, supabase::ping()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(String::from("supabase timed out")),
+    }
+}
+
+async fn check_starknet() -> Result<(), String> {
+    let provider = StarkNetProvider::new().provider;
+
+    match tokio::time::timeout(CHECK_TIMEOUT, provider.get_block(BlockId::Latest)).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(String::from("starknet gateway timed out")),
+    }
+}
+
+/// Spawns the task running the checks every `HEALTH_CHECK_INTERVAL_SECS`.
+/// `services` are the names of the gRPC services whose status follows the
+/// server's.
+pub fn start(mut reporter: HealthReporter, services: Vec<&'static str>) {
+    let interval = dotenv::var("HEALTH_CHECK_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        let mut last = None;
+
+        loop {
+            ticker.tick().await;
+
+            // the config is checked first, the other checks need it
+            let ready = match check_config() {
+                Ok(()) => check_supabase().await,
+                Err(e) => Err(e),
+            };
+
+            let status = match &ready {
+                Ok(()) => ServingStatus::Serving,
+                Err(e) => {
+                    warn!("not ready : {e}");
+                    ServingStatus::NotServing
+                }
+            };
+
+            if last != Some(status) {
+                info!("health status changed to {status:?}");
+                last = Some(status);
+            }
+
+            reporter.set_service_status("", status).await;
+            for service in &services {
+                reporter.set_service_status(service, status).await;
+            }
+
+            let starknet = match ready {
+                Ok(()) => match check_starknet().await {
+                    Ok(()) => ServingStatus::Serving,
+                    Err(e) => {
+                        warn!("starknet degraded : {e}");
+                        ServingStatus::NotServing
+                    }
+                },
+                Err(_) => ServingStatus::NotServing,
+            };
+
+            reporter
+                .set_service_status(STARKNET_COMPONENT, starknet)
+                .await;
+        }
+    });
+}
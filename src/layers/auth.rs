@@ -0,0 +1,201 @@
+use std::{
+    fmt,
+    task::{Context, Poll},
+};
+
+use hyper::{Body, HeaderMap};
+use serde_json::Value;
+use time::OffsetDateTime;
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::layers::logger;
+use crate::supabase::auth::AuthClient;
+
+/// What a user is allowed to do, from least to most privileged. Set through
+/// the `role` of the user's `app_metadata`, which only the service role can
+/// change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Member,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    fn of(role: Option<&str>, app_metadata: &Value) -> Self {
+        let role = app_metadata.get("role").and_then(Value::as_str).or(role);
+
+        match role {
+            Some("admin") => Self::Admin,
+            Some("moderator") => Self::Moderator,
+            _ => Self::Member,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Member => write!(f, "member"),
+            Self::Moderator => write!(f, "moderator"),
+            Self::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// The user of the access token sent in the `authorization` metadata of a
+/// request, added to the request extensions by [`AuthLayer`].
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub id: String,
+    pub role: Role,
+    pub app_metadata: Value,
+}
+
+impl AuthenticatedUser {
+    /// Returns the authenticated user of `request`, or `Unauthenticated` if
+    /// the request was sent without an access token.
+    pub fn of<T>(request: &tonic::Request<T>) -> Result<&Self, Status> {
+        request
+            .extensions()
+            .get::<Self>()
+            .ok_or_else(|| Status::unauthenticated("missing access token"))
+    }
+
+    /// Like [`AuthenticatedUser::of`], but also checks that the user has at
+    /// least `role`.
+    pub fn with_role<T>(request: &tonic::Request<T>, role: Role) -> Result<&Self, Status> {
+        let user = Self::of(request)?;
+
+        if user.role < role {
+            return Err(Status::permission_denied(format!(
+                "this requires the {role} role"
+            )));
+        }
+
+        Ok(user)
+    }
+
+    /// Like [`AuthenticatedUser::of`], but also checks that `user_id`, a user
+    /// named in the body of the request, is the authenticated user. Ids in the
+    /// body can be set to anything, so they are only trusted once they match.
+    pub fn acting_as<'a, T>(
+        request: &'a tonic::Request<T>,
+        user_id: &str,
+    ) -> Result<&'a Self, Status> {
+        let user = Self::of(request)?;
+
+        if user.id != user_id {
+            return Err(Status::permission_denied(
+                "the access token does not belong to this user",
+            ));
+        }
+
+        Ok(user)
+    }
+
+    /// Resolves the user of `access_token` through GoTrue.
+    pub async fn from_token(client: &AuthClient, access_token: &str) -> Result<Self, Status> {
+        match client.get_user(access_token).await {
+            Ok(Some(user)) => {
+                logger::record_user_id(&user.id);
+
+                // GoTrue only stops a banned user from getting new tokens
+                if user.is_banned_at(OffsetDateTime::now_utc()) {
+                    return Err(Status::permission_denied("this account is suspended"));
+                }
+
+                Ok(Self {
+                    role: Role::of(user.role.as_deref(), &user.app_metadata),
+                    id: user.id,
+                    app_metadata: user.app_metadata,
+                })
+            }
+
+            Ok(None) => Err(Status::unauthenticated("invalid or expired access token")),
+
+            Err(e) => {
+                warn!("unable to verify access token error={e}");
+                Err(Status::unavailable("unable to verify access token"))
+            }
+        }
+    }
+}
+
+/// The token of the `authorization: Bearer <token>` header, if any.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Resolves the user of the `authorization: Bearer <token>` metadata through
+/// GoTrue. Requests without a token are let through, so that every RPC can
+/// decide whether it requires one. Requests with an invalid token are
+/// rejected.
+#[derive(Clone)]
+pub struct AuthLayer {
+    client: AuthClient,
+}
+
+impl AuthLayer {
+    pub fn new() -> Self {
+        Self {
+            client: AuthClient::new(),
+        }
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Authenticate<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Authenticate {
+            inner,
+            client: self.client.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Authenticate<S> {
+    inner: S,
+    client: AuthClient,
+}
+
+impl<S> Service<hyper::Request<Body>> for Authenticate<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: hyper::Request<Body>) -> Self::Future {
+        // See `RequestLogger` for why the inner service is swapped out
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let client = self.client.clone();
+
+        Box::pin(async move {
+            if let Some(token) = bearer_token(req.headers()).map(str::to_owned) {
+                match AuthenticatedUser::from_token(&client, &token).await {
+                    Ok(user) => {
+                        req.extensions_mut().insert(user);
+                    }
+                    Err(status) => return Ok(status.to_http()),
+                }
+            }
+
+            inner.call(req).await
+        })
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{
+    body::{Bytes, HttpBody, SizeHint},
+    HeaderMap,
+};
+use tonic::{body::BoxBody, Status};
+
+type OnEnd = Box<dyn FnOnce(Option<String>) + Send>;
+
+/// A response body that reports the `grpc-status` of the call once it was
+/// sent whole, as the status of a successful call is only in the trailers.
+/// `on_end` gets `None` when the body is dropped before its end, e.g. because
+/// the client went away.
+///
+/// gRPC-Web responses carry their trailers in the body and HTTP/1.1 doesn't
+/// send trailers at all, so for those only a status sent in the headers is
+/// seen, and anything else is reported as `0`.
+pub(super) struct StatusBody {
+    inner: BoxBody,
+    // set for trailers-only responses, i.e. most failed calls
+    header_status: Option<String>,
+    data_done: bool,
+    on_end: Option<OnEnd>,
+}
+
+impl StatusBody {
+    pub(super) fn wrap<F>(res: hyper::Response<BoxBody>, on_end: F) -> hyper::Response<BoxBody>
+    where
+        F: FnOnce(Option<String>) + Send + 'static,
+    {
+        let header_status = status_of(res.headers());
+
+        res.map(|inner| {
+            tonic::body::boxed(Self {
+                inner,
+                header_status,
+                data_done: false,
+                on_end: Some(Box::new(on_end)),
+            })
+        })
+    }
+
+    fn end(&mut self, status: Option<String>) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(status);
+        }
+    }
+
+    fn status_or_default(&self, status: Option<String>) -> String {
+        status
+            .or_else(|| self.header_status.clone())
+            .unwrap_or_else(|| String::from("0"))
+    }
+}
+
+fn status_of(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("grpc-status")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned)
+}
+
+fn code_of(status: &Status) -> String {
+    (status.code() as i32).to_string()
+}
+
+impl HttpBody for StatusBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+
+        match &poll {
+            Poll::Ready(Some(Err(status))) => {
+                let code = code_of(status);
+                self.end(Some(code));
+            }
+            Poll::Ready(None) => self.data_done = true,
+            _ => {}
+        }
+
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let poll = Pin::new(&mut self.inner).poll_trailers(cx);
+
+        match &poll {
+            Poll::Ready(Ok(trailers)) => {
+                let status = self.status_or_default(trailers.as_ref().and_then(status_of));
+                self.end(Some(status));
+            }
+            Poll::Ready(Err(status)) => {
+                let code = code_of(status);
+                self.end(Some(code));
+            }
+            Poll::Pending => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for StatusBody {
+    fn drop(&mut self) {
+        // The trailers aren't polled over HTTP/1.1, nor for an empty body
+        if self.data_done || self.inner.is_end_stream() {
+            let status = self.status_or_default(None);
+            self.end(Some(status));
+        } else {
+            self.end(None);
+        }
+    }
+}
@@ -0,0 +1,557 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    body::{Bytes, HttpBody},
+    header::{HeaderName, HeaderValue},
+    Body, HeaderMap,
+};
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+use tracing::{info, warn};
+
+use crate::layers::{self, auth::AuthenticatedUser, rate_limit};
+use crate::supabase::idempotency::{IdempotencyClient, IdempotencyRecord};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const DEFAULT_CAPACITY: usize = 10_000;
+const DEFAULT_TTL_HOURS: u64 = 24;
+
+// Server streaming methods, whose responses can't be buffered whole. A key
+// sent along with them is ignored.
+const STREAMING_METHODS: &[&str] = &[
+    "/user.User/ExportTransactionHistory",
+    "/grpc.health.v1.Health/Watch",
+    "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+];
+
+enum Entry {
+    InFlight {
+        fingerprint: String,
+        started_at: Instant,
+    },
+    Done {
+        record: IdempotencyRecord,
+        stored_at: Instant,
+    },
+}
+
+enum Lookup {
+    Vacant,
+    InFlight,
+    Replay(IdempotencyRecord),
+    Mismatch,
+}
+
+/// Responses of requests made with an idempotency key, kept in memory and,
+/// when `IDEMPOTENCY_PERSIST=true`, in the `idempotency_keys` table so that
+/// they survive a restart.
+pub struct IdempotencyStore {
+    entries: Mutex<LruCache<String, Entry>>,
+    ttl: Duration,
+    table: Option<IdempotencyClient>,
+}
+
+impl IdempotencyStore {
+    pub fn from_env() -> Self {
+        let capacity = dotenv::var("IDEMPOTENCY_CACHE_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .and_then(NonZeroUsize::new)
+            .unwrap_or(NonZeroUsize::new(DEFAULT_CAPACITY).unwrap());
+
+        let ttl = dotenv::var("IDEMPOTENCY_TTL_HOURS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TTL_HOURS);
+
+        let table = dotenv::var("IDEMPOTENCY_PERSIST")
+            .map(|v| v == "true")
+            .unwrap_or(false)
+            .then(IdempotencyClient::new);
+
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl: Duration::from_secs(ttl * 60 * 60),
+            table,
+        }
+    }
+
+    fn check(
+        &self,
+        entries: &mut LruCache<String, Entry>,
+        key: &str,
+        fingerprint: &str,
+    ) -> Option<Lookup> {
+        let lookup = match entries.get(key)? {
+            Entry::InFlight { started_at, .. }
+            | Entry::Done {
+                stored_at: started_at,
+                ..
+            } if started_at.elapsed() > self.ttl => None,
+            Entry::InFlight { fingerprint: f, .. } if f == fingerprint => Some(Lookup::InFlight),
+            Entry::Done { record, .. } if record.fingerprint == fingerprint => {
+                Some(Lookup::Replay(record.clone()))
+            }
+            _ => Some(Lookup::Mismatch),
+        };
+
+        if lookup.is_none() {
+            entries.pop(key);
+        }
+
+        lookup
+    }
+
+    /// Looks `key` up, and marks it as in flight if it hasn't been used yet.
+    async fn begin(&self, key: &str, fingerprint: &str) -> Lookup {
+        if let Some(lookup) = self.check(&mut self.entries.lock().unwrap(), key, fingerprint) {
+            return lookup;
+        }
+
+        if let Some(table) = &self.table {
+            let since = OffsetDateTime::now_utc() - self.ttl;
+            let since = since.format(&Rfc3339).unwrap_or_default();
+
+            match table.get(key, since).await {
+                Ok(Some(record)) => {
+                    let lookup = if record.fingerprint == fingerprint {
+                        Lookup::Replay(record.clone())
+                    } else {
+                        Lookup::Mismatch
+                    };
+
+                    self.entries.lock().unwrap().put(
+                        key.to_owned(),
+                        Entry::Done {
+                            record,
+                            stored_at: Instant::now(),
+                        },
+                    );
+
+                    return lookup;
+                }
+                Ok(None) => {}
+                Err(e) => warn!("unable to look up idempotency key error={e}"),
+            }
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        // another request with the same key may have come in meanwhile
+        if let Some(lookup) = self.check(&mut entries, key, fingerprint) {
+            return lookup;
+        }
+
+        entries.put(
+            key.to_owned(),
+            Entry::InFlight {
+                fingerprint: fingerprint.to_owned(),
+                started_at: Instant::now(),
+            },
+        );
+
+        Lookup::Vacant
+    }
+
+    /// Forgets an in flight key, so that a failed request can be retried.
+    /// Keys that were completed are kept.
+    fn abandon(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(Entry::InFlight { .. }) = entries.peek(key) {
+            entries.pop(key);
+        }
+    }
+
+    async fn complete(&self, record: IdempotencyRecord) {
+        if let Some(table) = &self.table {
+            if let Err(e) = table.insert(&record).await {
+                warn!("unable to persist idempotency key error={e}");
+            }
+        }
+
+        self.entries.lock().unwrap().put(
+            record.key.clone(),
+            Entry::Done {
+                record,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Runs `call` at most once per `key`, for callers outside of the gRPC
+    /// stack such as the REST gateway. The payload is fingerprinted as the
+    /// layer does with request bodies, and the response is stored as is.
+    pub async fn call<F>(
+        self: &Arc<Self>,
+        key: String,
+        payload: &[u8],
+        call: F,
+    ) -> Result<Bytes, Status>
+    where
+        F: Future<Output = Result<Bytes, Status>>,
+    {
+        let fingerprint = format!("{:x}", Sha256::digest(payload));
+
+        match self.begin(&key, &fingerprint).await {
+            Lookup::Vacant => {}
+            Lookup::Replay(record) => {
+                info!("replaying response for idempotency key={key}");
+                return Ok(Bytes::from(
+                    base64::decode(&record.body).unwrap_or_default(),
+                ));
+            }
+            Lookup::InFlight => return Err(in_flight()),
+            Lookup::Mismatch => return Err(mismatch()),
+        }
+
+        let _guard = InFlightGuard {
+            store: self.clone(),
+            key: key.clone(),
+        };
+
+        let data = call.await?;
+
+        self.complete(IdempotencyRecord {
+            key,
+            fingerprint,
+            headers: Vec::new(),
+            body: base64::encode(&data),
+            trailers: Vec::new(),
+        })
+        .await;
+
+        Ok(data)
+    }
+}
+
+fn in_flight() -> Status {
+    Status::aborted("a request with this idempotency key is still in progress")
+}
+
+fn mismatch() -> Status {
+    Status::invalid_argument("idempotency key was already used with a different payload")
+}
+
+/// Abandons an in flight key when dropped, unless the request was completed
+/// by then. This covers every way a request can end early, including the
+/// client disconnecting and the future being dropped.
+struct InFlightGuard {
+    store: Arc<IdempotencyStore>,
+    key: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.store.abandon(&self.key);
+    }
+}
+
+/// Makes requests sent with an `idempotency-key` metadata safe to retry. The
+/// first successful response for a key is stored and returned again for any
+/// request repeating the key with the same payload. Reusing a key with a
+/// different payload is rejected. Keys are scoped to the RPC and the caller,
+/// or the caller's IP address for requests without an access token.
+///
+/// Responses are buffered whole, so keys are ignored on the server streaming
+/// methods, and on gRPC-Web text requests whose status can't be read.
+#[derive(Clone)]
+pub struct IdempotencyLayer {
+    store: Arc<IdempotencyStore>,
+}
+
+impl IdempotencyLayer {
+    pub fn new() -> Self {
+        Self {
+            store: Arc::new(IdempotencyStore::from_env()),
+        }
+    }
+
+    /// The store behind the layer, shared with the REST gateway so that its
+    /// requests can be retried safely too.
+    pub fn store(&self) -> Arc<IdempotencyStore> {
+        self.store.clone()
+    }
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = Idempotency<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Idempotency {
+            inner,
+            store: self.store.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Idempotency<S> {
+    inner: S,
+    store: Arc<IdempotencyStore>,
+}
+
+impl<S> Service<hyper::Request<Body>> for Idempotency<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        // See `RequestLogger` for why the inner service is swapped out
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let store = self.store.clone();
+
+        Box::pin(async move {
+            // gRPC-Web text responses are base64 encoded, status included
+            let grpc_web_text = req
+                .headers()
+                .get(hyper::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with("application/grpc-web-text"));
+
+            if grpc_web_text || STREAMING_METHODS.contains(&req.uri().path()) {
+                return inner.call(req).await;
+            }
+
+            let key = req
+                .headers()
+                .get(IDEMPOTENCY_KEY_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty());
+
+            let Some(key) = key else {
+                return inner.call(req).await;
+            };
+
+            let caller = rate_limit::client_key(
+                req.extensions().get::<AuthenticatedUser>(),
+                layers::client_ip(req.headers(), req.extensions()),
+            );
+
+            let key = format!("{caller}:{}:{key}", req.uri().path());
+
+            let (parts, body) = req.into_parts();
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => return Ok(Status::internal(e.to_string()).to_http()),
+            };
+
+            let fingerprint = format!("{:x}", Sha256::digest(&body));
+
+            match store.begin(&key, &fingerprint).await {
+                Lookup::Vacant => {}
+                Lookup::Replay(record) => {
+                    info!("replaying response for idempotency key={key}");
+                    return Ok(replay(record));
+                }
+                Lookup::InFlight => return Ok(in_flight().to_http()),
+                Lookup::Mismatch => return Ok(mismatch().to_http()),
+            }
+
+            let _guard = InFlightGuard {
+                store: store.clone(),
+                key: key.clone(),
+            };
+
+            let res = inner
+                .call(hyper::Request::from_parts(parts, Body::from(body)))
+                .await?;
+
+            let (parts, mut body) = res.into_parts();
+
+            let mut data = Vec::new();
+            while let Some(chunk) = body.data().await {
+                match chunk {
+                    Ok(chunk) => data.extend_from_slice(&chunk),
+                    Err(status) => return Ok(status.to_http()),
+                }
+            }
+
+            let trailers = match body.trailers().await {
+                Ok(trailers) => trailers,
+                Err(status) => return Ok(status.to_http()),
+            };
+
+            // an error is sent as a trailers-only response, with the status
+            // in the headers, and gRPC-Web sends its trailers in the body
+            let succeeded = trailers
+                .as_ref()
+                .and_then(|t| t.get("grpc-status"))
+                .or_else(|| parts.headers.get("grpc-status"))
+                .map(|status| status == "0")
+                .or_else(|| grpc_web_status(&data).map(|status| status == "0"))
+                .unwrap_or_default();
+
+            let data = Bytes::from(data);
+
+            if succeeded {
+                store
+                    .complete(IdempotencyRecord {
+                        key,
+                        fingerprint,
+                        headers: to_pairs(&parts.headers),
+                        body: base64::encode(&data),
+                        trailers: trailers.as_ref().map(to_pairs).unwrap_or_default(),
+                    })
+                    .await;
+            }
+
+            Ok(hyper::Response::from_parts(
+                parts,
+                tonic::body::boxed(ReplayBody::new(data, trailers)),
+            ))
+        })
+    }
+}
+
+fn replay(record: IdempotencyRecord) -> hyper::Response<BoxBody> {
+    let data = base64::decode(&record.body).unwrap_or_default();
+    let body = ReplayBody::new(Bytes::from(data), Some(from_pairs(&record.trailers)));
+
+    let mut res = hyper::Response::new(tonic::body::boxed(body));
+    *res.headers_mut() = from_pairs(&record.headers);
+    res
+}
+
+/// The `grpc-status` of the trailer frame of a gRPC-Web response body, which
+/// is made of frames of a flag byte, a 4 byte length and the data. The
+/// trailer frame has the most significant bit of its flag set, and holds the
+/// trailers as HTTP/1.1 header lines.
+fn grpc_web_status(mut body: &[u8]) -> Option<&str> {
+    while body.len() >= 5 {
+        let flag = body[0];
+        let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        let frame = body.get(5..5 + len)?;
+
+        if flag & 0x80 != 0 {
+            return std::str::from_utf8(frame)
+                .ok()?
+                .split("\r\n")
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("grpc-status"))
+                .map(|(_, value)| value.trim());
+        }
+
+        body = &body[5 + len..];
+    }
+
+    None
+}
+
+fn to_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+        .collect()
+}
+
+fn from_pairs(pairs: &[(String, String)]) -> HeaderMap {
+    pairs
+        .iter()
+        .filter_map(|(name, value)| {
+            Some((
+                HeaderName::from_bytes(name.as_bytes()).ok()?,
+                HeaderValue::from_str(value).ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// A response body that was read whole, to be sent again.
+struct ReplayBody {
+    data: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl ReplayBody {
+    fn new(data: Bytes, trailers: Option<HeaderMap>) -> Self {
+        Self {
+            data: Some(data).filter(|d| !d.is_empty()),
+            trailers,
+        }
+    }
+}
+
+impl HttpBody for ReplayBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Poll::Ready(self.data.take().map(Ok))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none() && self.trailers.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(flag: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![flag];
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    #[test]
+    fn reads_the_status_of_the_trailer_frame() {
+        let mut body = frame(0, b"message");
+        body.extend(frame(0x80, b"grpc-status:0\r\ngrpc-message:\r\n"));
+
+        assert_eq!(grpc_web_status(&body), Some("0"));
+    }
+
+    #[test]
+    fn reads_the_status_regardless_of_case_and_spacing() {
+        let body = frame(0x80, b"Grpc-Message: nope\r\nGRPC-STATUS: 5\r\n");
+
+        assert_eq!(grpc_web_status(&body), Some("5"));
+    }
+
+    #[test]
+    fn no_status_without_a_trailer_frame() {
+        assert_eq!(grpc_web_status(&frame(0, b"grpc-status:0")), None);
+        assert_eq!(grpc_web_status(b""), None);
+    }
+
+    #[test]
+    fn no_status_in_a_truncated_body() {
+        let mut body = frame(0x80, b"grpc-status:0\r\n");
+        body.truncate(body.len() - 4);
+
+        assert_eq!(grpc_web_status(&body), None);
+    }
+}
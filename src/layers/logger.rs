@@ -1,10 +1,62 @@
-use std::task::{Context, Poll};
+use std::{
+    future::Future,
+    task::{Context, Poll},
+    time::Instant,
+};
 
-use hyper::Body;
+use futures::FutureExt;
+use hyper::{header::HeaderValue, Body, HeaderMap};
+use opentelemetry::{global, propagation::Extractor};
 use tonic::body::BoxBody;
 use tower::{Layer, Service};
-use tracing::info;
+use tracing::{field, info, info_span, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
 
+use crate::layers::{self, body::StatusBody};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, to be forwarded to outgoing calls.
+/// `None` outside of an RPC, e.g. in the scheduler.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Runs `fut` as part of the request `request_id`, for requests that don't go
+/// through [`RequestLogger`].
+pub fn scope_request_id<F: Future>(request_id: String, fut: F) -> impl Future<Output = F::Output> {
+    REQUEST_ID.scope(request_id, fut)
+}
+
+/// Records the caller on the span of the current RPC, once it is known.
+pub fn record_user_id(user_id: &str) {
+    Span::current().record("user_id", user_id);
+}
+
+// Reads the W3C `traceparent`/`tracestate` of an incoming request.
+struct MetadataExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for MetadataExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Runs every RPC in its own span, tagged with the `x-request-id` sent by the
+/// client (or a new one), the peer address and, once authenticated, the user
+/// id. The span continues the trace of the W3C trace context sent by the
+/// client, if any. The id is sent back in the response headers. Once the
+/// response body was sent whole, the duration and `grpc-status` are logged,
+/// with the status read from the trailers (see [`StatusBody`] for gRPC-Web).
 #[derive(Debug, Clone, Default)]
 pub struct RequestLoggerLayer;
 
@@ -34,20 +86,79 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: hyper::Request<Body>) -> Self::Future {
         // This is necessary because tonic internally uses `tower::buffer::Buffer`.
         // See https://github.com/tower-rs/tower/issues/547#issuecomment-767629149
         // for details on why this is necessary
         let clone = self.inner.clone();
         let mut inner = std::mem::replace(&mut self.inner, clone);
 
-        Box::pin(async move {
-            // Do extra async work here...
-            let uri = req.uri().path();
-            let method = req.method();
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            req.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+
+        let peer = layers::remote_addr(req.extensions()).map(|addr| addr.to_string());
+
+        let span = info_span!(
+            "rpc",
+            method = req.uri().path(),
+            request_id = request_id.as_str(),
+            peer = peer.as_deref(),
+            user_id = field::Empty,
+            grpc_status = field::Empty,
+            duration_ms = field::Empty,
+        );
+
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&MetadataExtractor(req.headers()))
+        });
+        span.set_parent(parent);
+
+        let response_id = request_id.clone();
+
+        let fut = async move {
+            let start = Instant::now();
+            let res = inner.call(req).await;
+
+            match res {
+                Ok(mut res) => {
+                    if let Ok(value) = HeaderValue::from_str(&response_id) {
+                        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+                    }
+
+                    let span = Span::current();
+
+                    Ok(StatusBody::wrap(res, move |grpc_status| {
+                        let _enter = span.enter();
+                        let duration_ms = start.elapsed().as_millis() as u64;
+                        span.record("duration_ms", duration_ms);
+
+                        match grpc_status {
+                            Some(grpc_status) => {
+                                span.record("grpc_status", grpc_status.as_str());
+                                info!(%grpc_status, duration_ms, "finished");
+                            }
+                            None => info!(duration_ms, "response dropped before its end"),
+                        }
+                    }))
+                }
+                Err(e) => {
+                    let duration_ms = start.elapsed().as_millis() as u64;
+                    Span::current().record("duration_ms", duration_ms);
+                    info!(duration_ms, "failed");
+                    Err(e)
+                }
+            }
+        };
 
-            info!(?method, ?uri);
-            inner.call(req).await
-        })
+        REQUEST_ID.scope(request_id, fut.instrument(span)).boxed()
     }
 }
@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use hyper::{body::HttpBody, Body};
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+use tracing::warn;
+
+/// The update RPCs only take a few fields, so they get much less room than
+/// the default.
+const DEFAULT_MESSAGE_SIZES: &str = "servicerequest.ServiceRequest/Update=64KiB,\
+    rating.Rating/Update=16KiB,user.User/Update=64KiB";
+/// Same as the gRPC default.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+
+    let multiplier = match unit.trim() {
+        "" | "B" => 1,
+        "KiB" => 1024,
+        "MiB" => 1024 * 1024,
+        _ => {
+            return Err(format!(
+                "invalid unit in size `{s}`, expected B, KiB or MiB"
+            ))
+        }
+    };
+
+    number
+        .parse::<usize>()
+        .map(|n| n * multiplier)
+        .map_err(|_| format!("invalid size `{s}`"))
+}
+
+/// The largest request body each method accepts, from `MESSAGE_SIZES`, a
+/// comma separated list of `<package>.<Service>/<Method>=<size>`, e.g.
+/// `user.User/Update=64KiB`. Other methods accept up to `MAX_MESSAGE_SIZE`.
+pub struct MessageSizes {
+    sizes: HashMap<String, usize>,
+    default: usize,
+}
+
+impl MessageSizes {
+    pub fn from_env() -> Self {
+        let sizes =
+            dotenv::var("MESSAGE_SIZES").unwrap_or_else(|_| DEFAULT_MESSAGE_SIZES.to_owned());
+
+        let mut parsed_sizes = HashMap::new();
+
+        for size in sizes.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let parsed = size
+                .split_once('=')
+                .ok_or_else(|| format!("invalid message size `{size}`"))
+                .and_then(|(method, size)| Ok((method.trim(), parse_size(size)?)));
+
+            match parsed {
+                Ok((method, size)) => {
+                    parsed_sizes.insert(format!("/{}", method.trim_start_matches('/')), size);
+                }
+                Err(e) => warn!("ignoring message size error={e}"),
+            }
+        }
+
+        let default = match dotenv::var("MAX_MESSAGE_SIZE").map(|v| parse_size(&v)) {
+            Ok(Ok(size)) => size,
+            Ok(Err(e)) => {
+                warn!("ignoring max message size error={e}");
+                DEFAULT_MAX_MESSAGE_SIZE
+            }
+            Err(_) => DEFAULT_MAX_MESSAGE_SIZE,
+        };
+
+        Self {
+            sizes: parsed_sizes,
+            default,
+        }
+    }
+
+    fn max_for(&self, path: &str) -> usize {
+        self.sizes.get(path).copied().unwrap_or(self.default)
+    }
+}
+
+/// Rejects requests whose body is larger than the size allowed for their
+/// method with `ResourceExhausted`, before they are authenticated or decoded.
+/// The size is that of the body as received, frames included.
+#[derive(Clone)]
+pub struct MessageSizeLayer {
+    sizes: Arc<MessageSizes>,
+}
+
+impl MessageSizeLayer {
+    pub fn new() -> Self {
+        Self {
+            sizes: Arc::new(MessageSizes::from_env()),
+        }
+    }
+}
+
+impl<S> Layer<S> for MessageSizeLayer {
+    type Service = MessageSize<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MessageSize {
+            inner,
+            sizes: self.sizes.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MessageSize<S> {
+    inner: S,
+    sizes: Arc<MessageSizes>,
+}
+
+impl<S> Service<hyper::Request<Body>> for MessageSize<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        // See `RequestLogger` for why the inner service is swapped out
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let max = self.sizes.max_for(req.uri().path());
+
+        Box::pin(async move {
+            let too_large = || {
+                Status::resource_exhausted(format!("request message is larger than {max} bytes"))
+                    .to_http()
+            };
+
+            let content_length = req
+                .headers()
+                .get(hyper::header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<usize>().ok());
+
+            if content_length.map_or(false, |len| len > max) {
+                return Ok(too_large());
+            }
+
+            // Every RPC takes a single request message, so the body can be
+            // read whole
+            let (parts, mut body) = req.into_parts();
+            let mut data = Vec::new();
+
+            while let Some(chunk) = body.data().await {
+                match chunk {
+                    Ok(chunk) if data.len() + chunk.len() > max => return Ok(too_large()),
+                    Ok(chunk) => data.extend_from_slice(&chunk),
+                    Err(e) => return Ok(Status::internal(e.to_string()).to_http()),
+                }
+            }
+
+            inner
+                .call(hyper::Request::from_parts(parts, Body::from(data)))
+                .await
+        })
+    }
+}
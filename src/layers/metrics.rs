@@ -0,0 +1,77 @@
+use std::{
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use hyper::Body;
+use tonic::body::BoxBody;
+use tower::{Layer, Service};
+
+use crate::layers::policy;
+use crate::metrics::{RPC_DURATION, RPC_REQUESTS};
+
+/// Counts RPCs by method and gRPC status code, and times them. Paths that
+/// aren't a known method are counted as `unknown`. Like
+/// `RequestLogger`, the status is read from the response headers, where it
+/// only is for failed calls, so anything else is counted as `0`.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metrics { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Metrics<S> {
+    inner: S,
+}
+
+impl<S> Service<hyper::Request<Body>> for Metrics<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        // See `RequestLogger` for why the inner service is swapped out
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            // any path can be requested, so only RPCs get their own series
+            let method = policy::known_method(req.uri().path()).unwrap_or("unknown");
+            let start = Instant::now();
+
+            let res = inner.call(req).await;
+
+            RPC_DURATION
+                .with_label_values(&[method])
+                .observe(start.elapsed().as_secs_f64());
+
+            let code = match &res {
+                Ok(res) => res
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("0")
+                    .to_owned(),
+                Err(_) => String::from("transport_error"),
+            };
+
+            RPC_REQUESTS.with_label_values(&[method, &code]).inc();
+
+            res
+        })
+    }
+}
@@ -1 +1,146 @@
+pub mod auth;
+mod body;
+pub mod idempotency;
 pub mod logger;
+pub mod message_size;
+pub mod metrics;
+pub mod policy;
+pub mod rate_limit;
+
+use std::net::{IpAddr, SocketAddr};
+
+use hyper::{http::Extensions, HeaderMap};
+use once_cell::sync::Lazy;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tracing::warn;
+
+/// The proxies whose `X-Forwarded-For` is trusted, read from
+/// `TRUSTED_PROXIES` as a comma separated list of IP addresses. The header is
+/// ignored when it isn't set, as any client can send it.
+static TRUSTED_PROXIES: Lazy<Vec<IpAddr>> = Lazy::new(|| {
+    dotenv::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| match p.parse() {
+            Ok(ip) => Some(ip),
+            Err(_) => {
+                warn!("ignoring trusted proxy `{p}`, expected an IP address");
+                None
+            }
+        })
+        .collect()
+});
+
+/// The address of the client of a request, whether it was sent over TLS or
+/// not.
+pub fn remote_addr(extensions: &Extensions) -> Option<SocketAddr> {
+    match extensions.get::<TcpConnectInfo>() {
+        Some(info) => info.remote_addr(),
+        None => extensions
+            .get::<TlsConnectInfo<TcpConnectInfo>>()
+            .and_then(|info| info.get_ref().remote_addr()),
+    }
+}
+
+/// The IP address of the client of a request, see [`client_ip_from`].
+pub fn client_ip(headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    client_ip_from(headers, remote_addr(extensions))
+}
+
+/// The IP address of the client connected from `peer`. When `peer` is one of
+/// `TRUSTED_PROXIES`, it is the last address of `X-Forwarded-For` that isn't,
+/// as the addresses before it could have been made up by the client.
+pub fn client_ip_from(headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+    peer.map(|peer| resolve_client_ip(headers, peer.ip(), &TRUSTED_PROXIES))
+}
+
+fn resolve_client_ip(headers: &HeaderMap, peer: IpAddr, trusted: &[IpAddr]) -> IpAddr {
+    let mut ip = peer;
+
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect::<Vec<_>>();
+
+    for hop in forwarded.into_iter().rev() {
+        if !trusted.contains(&ip) {
+            break;
+        }
+
+        match hop.trim().parse() {
+            Ok(hop) => ip = hop,
+            Err(_) => break,
+        }
+    }
+
+    ip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1";
+    const CLIENT: &str = "203.0.113.7";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn ignores_the_header_from_untrusted_peers() {
+        let headers = forwarded_for(&[CLIENT]);
+
+        assert_eq!(resolve_client_ip(&headers, ip(PROXY), &[]), ip(PROXY));
+    }
+
+    #[test]
+    fn uses_the_header_from_trusted_proxies() {
+        let headers = forwarded_for(&[CLIENT]);
+
+        assert_eq!(
+            resolve_client_ip(&headers, ip(PROXY), &[ip(PROXY)]),
+            ip(CLIENT)
+        );
+    }
+
+    #[test]
+    fn skips_addresses_added_by_the_client() {
+        let headers = forwarded_for(&["192.0.2.1, 198.51.100.2", CLIENT]);
+
+        assert_eq!(
+            resolve_client_ip(&headers, ip(PROXY), &[ip(PROXY)]),
+            ip(CLIENT)
+        );
+    }
+
+    #[test]
+    fn walks_through_chained_proxies() {
+        let headers = forwarded_for(&["192.0.2.1", &format!("{CLIENT}, 10.0.0.2")]);
+        let trusted = [ip(PROXY), ip("10.0.0.2")];
+
+        assert_eq!(resolve_client_ip(&headers, ip(PROXY), &trusted), ip(CLIENT));
+    }
+
+    #[test]
+    fn stops_at_an_invalid_address() {
+        let headers = forwarded_for(&["unknown"]);
+
+        assert_eq!(
+            resolve_client_ip(&headers, ip(PROXY), &[ip(PROXY)]),
+            ip(PROXY)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::task::{Context, Poll};
+
+use hyper::Body;
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+
+use crate::layers::auth::{AuthenticatedUser, Role};
+
+/// Who can call a method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// Anyone, with or without an access token.
+    Anonymous,
+    /// Users with at least this role.
+    AtLeast(Role),
+}
+
+const ANONYMOUS: Access = Access::Anonymous;
+const MEMBER: Access = Access::AtLeast(Role::Member);
+const MODERATOR: Access = Access::AtLeast(Role::Moderator);
+const ADMIN: Access = Access::AtLeast(Role::Admin);
+
+/// Who can call each method. A path ending with `/` applies to every method
+/// of the service, and is only used for services that aren't ours. Methods
+/// that aren't listed can't be called at all, so every new RPC has to be
+/// given a policy here.
+const POLICIES: &[(&str, Access)] = &[
+    ("/auth.Auth/SignUp", ANONYMOUS),
+    ("/grpc.health.v1.Health/", ANONYMOUS),
+    ("/grpc.reflection.v1alpha.ServerReflection/", ANONYMOUS),
+    ("/servicerequest.ServiceRequest/Create", MEMBER),
+    ("/servicerequest.ServiceRequest/Update", MEMBER),
+    ("/servicerequest.ServiceRequest/Delete", MEMBER),
+    ("/servicerequest.ServiceRequest/Get", MEMBER),
+    ("/servicerequest.ServiceRequest/GetById", MEMBER),
+    ("/servicerequest.ServiceRequest/GetAvailable", MEMBER),
+    ("/servicerequest.ServiceRequest/GetSummaryForUser", MEMBER),
+    ("/servicerequest.ServiceRequest/ApplyProvider", MEMBER),
+    ("/servicerequest.ServiceRequest/WithdrawApplication", MEMBER),
+    ("/servicerequest.ServiceRequest/SelectProvider", MEMBER),
+    ("/servicerequest.ServiceRequest/StartService", MEMBER),
+    ("/servicerequest.ServiceRequest/CompleteService", MEMBER),
+    ("/servicerequest.ServiceRequest/Cancel", MEMBER),
+    ("/servicerequest.ServiceRequest/OpenDispute", MEMBER),
+    ("/servicerequest.ServiceRequest/ResolveDispute", ADMIN),
+    ("/servicerequest.ServiceRequest/StartTimer", MEMBER),
+    ("/servicerequest.ServiceRequest/StopTimer", MEMBER),
+    ("/servicerequest.ServiceRequest/AddTimeEntry", MEMBER),
+    ("/servicerequest.ServiceRequest/GetTimeLog", MEMBER),
+    ("/servicerequest.ServiceRequest/ConfirmTimeLog", MEMBER),
+    ("/servicerequest.ServiceRequest/UpdateSeries", MEMBER),
+    ("/servicerequest.ServiceRequest/CancelSeries", MEMBER),
+    ("/rating.Rating/CreateForRequestor", MEMBER),
+    ("/rating.Rating/CreateForProvider", MEMBER),
+    ("/rating.Rating/GetForRequest", MEMBER),
+    ("/rating.Rating/GetById", MEMBER),
+    ("/rating.Rating/Get", MEMBER),
+    ("/rating.Rating/Update", MEMBER),
+    ("/rating.Rating/Delete", MEMBER),
+    ("/user.User/Get", MEMBER),
+    ("/user.User/GetById", MEMBER),
+    ("/user.User/Update", MEMBER),
+    ("/user.User/GetProfile", MEMBER),
+    ("/user.User/GetRating", MEMBER),
+    ("/user.User/GetCreditBalance", MEMBER),
+    ("/user.User/GetTransactionHistory", MEMBER),
+    ("/user.User/ExportTransactionHistory", MEMBER),
+    ("/user.User/TransferCredits", MEMBER),
+    ("/user.User/Report", MEMBER),
+    ("/organisation.Organisation/Create", MEMBER),
+    ("/organisation.Organisation/GetById", MEMBER),
+    ("/organisation.Organisation/GetMembers", MEMBER),
+    ("/organisation.Organisation/AddMember", MEMBER),
+    ("/organisation.Organisation/UpdateMemberRole", MEMBER),
+    ("/organisation.Organisation/RemoveMember", MEMBER),
+    ("/organisation.Organisation/GetCreditBalance", MEMBER),
+    ("/admin.Admin/SuspendUser", ADMIN),
+    ("/admin.Admin/UnsuspendUser", ADMIN),
+    ("/admin.Admin/ForceCancel", ADMIN),
+    ("/admin.Admin/AdjustCredits", ADMIN),
+    ("/admin.Admin/ListFlagged", MODERATOR),
+    ("/admin.Admin/GetModerationQueue", MODERATOR),
+    ("/admin.Admin/ResolveReport", MODERATOR),
+];
+
+// The entry `path` falls under: its own, or the one of its service.
+fn policy(path: &str) -> Option<&'static (&'static str, Access)> {
+    let exact = POLICIES.iter().find(|(p, _)| *p == path);
+
+    let service = || {
+        POLICIES
+            .iter()
+            .find(|(p, _)| p.ends_with('/') && path.starts_with(p))
+    };
+
+    exact.or_else(service)
+}
+
+fn access(path: &str) -> Option<Access> {
+    policy(path).map(|(_, access)| *access)
+}
+
+/// The entry of [`POLICIES`] that `path` falls under, which is the path itself
+/// for our methods and the service for the others. `None` for paths that
+/// aren't an RPC, so that they can be told apart without being echoed back.
+pub fn known_method(path: &str) -> Option<&'static str> {
+    policy(path).map(|(p, _)| *p)
+}
+
+/// Checks that `user` can call the method at `path`.
+pub fn check(path: &str, user: Option<&AuthenticatedUser>) -> Result<(), Status> {
+    let role = match access(path) {
+        Some(Access::Anonymous) => return Ok(()),
+        Some(Access::AtLeast(role)) => role,
+        None => {
+            return Err(Status::permission_denied(
+                "this method has no access policy",
+            ))
+        }
+    };
+
+    match user {
+        None => Err(Status::unauthenticated("missing access token")),
+        Some(user) if user.role < role => Err(Status::permission_denied(format!(
+            "this requires the {role} role"
+        ))),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Rejects calls made without an access token, or by a user whose role is
+/// below the one required, unless [`POLICIES`] lets anyone call the method.
+/// Handlers still check ownership of the data they change.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyLayer;
+
+impl<S> Layer<S> for PolicyLayer {
+    type Service = Policy<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Policy { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Policy<S> {
+    inner: S,
+}
+
+impl<S> Service<hyper::Request<Body>> for Policy<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        // See `RequestLogger` for why the inner service is swapped out
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            // CORS preflights are sent by browsers without the access token,
+            // they are answered by `tonic_web` before reaching any RPC.
+            if req.method() == hyper::Method::OPTIONS {
+                return inner.call(req).await;
+            }
+
+            if let Err(status) = check(req.uri().path(), req.extensions().get()) {
+                return Ok(status.to_http());
+            }
+
+            inner.call(req).await
+        })
+    }
+}
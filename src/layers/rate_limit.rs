@@ -0,0 +1,373 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use hyper::Body;
+use lru::LruCache;
+use tonic::{body::BoxBody, Status};
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::layers::{self, auth::AuthenticatedUser};
+use crate::services::error_messages;
+
+const DEFAULT_RATE_LIMITS: &str =
+    "auth.Auth/SignUp=5/hour,servicerequest.ServiceRequest/Create=30/hour";
+const DEFAULT_IP_RATE_LIMIT: &str = "600/minute";
+const DEFAULT_MAX_KEYS: usize = 100_000;
+
+// The bucket key of the quota of `IpRateLimiter`, which covers every method.
+const EVERY_METHOD: &str = "*";
+
+/// How many requests can be made to a method in a given period, e.g.
+/// `30/hour`. The whole quota can be used in a burst, after which it refills
+/// evenly over the period.
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+impl FromStr for Quota {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, period) = s
+            .split_once('/')
+            .ok_or_else(|| format!("invalid quota `{s}`, expected <count>/<period>"))?;
+
+        let count = count
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|c| *c > 0)
+            .ok_or_else(|| format!("invalid count in quota `{s}`"))?;
+
+        let period = match period.trim() {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            _ => return Err(format!("invalid period in quota `{s}`")),
+        };
+
+        Ok(Self {
+            capacity: count as f64,
+            refill_per_sec: count as f64 / period as f64,
+        })
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn full(quota: &Quota) -> Self {
+        Self {
+            tokens: quota.capacity,
+            updated_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token, or returns how long until one is available.
+    fn take(&mut self, quota: &Quota) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * quota.refill_per_sec).min(quota.capacity);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / quota.refill_per_sec,
+            ))
+        }
+    }
+}
+
+/// Per-method quotas, read from `RATE_LIMITS` as a comma separated list of
+/// `<package>.<Service>/<Method>=<quota>`. Methods without a quota are not
+/// limited.
+pub struct RateLimiter {
+    quotas: HashMap<String, Quota>,
+    buckets: Mutex<LruCache<(String, String), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn from_env() -> Self {
+        let limits = dotenv::var("RATE_LIMITS").unwrap_or_else(|_| DEFAULT_RATE_LIMITS.to_owned());
+
+        let mut quotas = HashMap::new();
+
+        for limit in limits.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+            let parsed = limit
+                .split_once('=')
+                .ok_or_else(|| format!("invalid rate limit `{limit}`"))
+                .and_then(|(method, quota)| Ok((method.trim(), quota.parse::<Quota>()?)));
+
+            match parsed {
+                Ok((method, quota)) => {
+                    quotas.insert(format!("/{}", method.trim_start_matches('/')), quota);
+                }
+                Err(e) => warn!("ignoring rate limit error={e}"),
+            }
+        }
+
+        Self::new(quotas)
+    }
+
+    /// A single quota for every method, read from `IP_RATE_LIMIT`. `None`
+    /// when it is set to `none`.
+    fn per_ip_from_env() -> Option<Self> {
+        let limit =
+            dotenv::var("IP_RATE_LIMIT").unwrap_or_else(|_| DEFAULT_IP_RATE_LIMIT.to_owned());
+
+        if limit.trim() == "none" {
+            return None;
+        }
+
+        let quota = match limit.parse::<Quota>() {
+            Ok(quota) => quota,
+            Err(e) => {
+                warn!("ignoring IP rate limit error={e}");
+                DEFAULT_IP_RATE_LIMIT.parse().unwrap()
+            }
+        };
+
+        Some(Self::new(HashMap::from([(EVERY_METHOD.to_owned(), quota)])))
+    }
+
+    fn new(quotas: HashMap<String, Quota>) -> Self {
+        let max_keys = dotenv::var("RATE_LIMIT_MAX_KEYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .and_then(NonZeroUsize::new)
+            .unwrap_or(NonZeroUsize::new(DEFAULT_MAX_KEYS).unwrap());
+
+        Self {
+            quotas,
+            buckets: Mutex::new(LruCache::new(max_keys)),
+        }
+    }
+
+    /// Takes a token from the bucket of `client` for `path`, or returns a
+    /// `ResourceExhausted` status with a `retry-after` (in seconds) when the
+    /// quota is used up.
+    pub fn check(&self, client: &str, path: &str) -> Result<(), Status> {
+        self.take(client, path).map_err(|wait| {
+            let retry_after = wait.as_secs_f64().ceil() as u64;
+
+            let mut status = Status::resource_exhausted(error_messages::TOO_MANY_REQUESTS);
+            if let Ok(value) = retry_after.to_string().parse() {
+                status.metadata_mut().insert("retry-after", value);
+            }
+
+            status
+        })
+    }
+
+    fn take(&self, client: &str, path: &str) -> Result<(), Duration> {
+        let Some(quota) = self.quotas.get(path) else {
+            return Ok(());
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let key = (client.to_owned(), path.to_owned());
+
+        if !buckets.contains(&key) {
+            buckets.put(key.clone(), Bucket::full(quota));
+        }
+
+        buckets.get_mut(&key).unwrap().take(quota)
+    }
+}
+
+/// The key a client's buckets are stored under: the user id when
+/// authenticated, the IP address otherwise.
+pub fn client_key(user: Option<&AuthenticatedUser>, ip: Option<IpAddr>) -> String {
+    match (user, ip) {
+        (Some(user), _) => format!("user:{}", user.id),
+        (None, Some(ip)) => format!("ip:{ip}"),
+        (None, None) => String::from("unknown"),
+    }
+}
+
+/// The quota of every IP address over all methods, checked before the caller
+/// is authenticated so that requests with made up access tokens are limited
+/// before they are looked up. It is meant to be much more generous than the
+/// quotas of [`RateLimiter`], as the users behind a NAT share it.
+#[derive(Clone)]
+pub struct IpRateLimiter {
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl IpRateLimiter {
+    pub fn from_env() -> Self {
+        Self {
+            limiter: RateLimiter::per_ip_from_env().map(Arc::new),
+        }
+    }
+
+    pub fn check(&self, ip: Option<IpAddr>) -> Result<(), Status> {
+        match &self.limiter {
+            Some(limiter) => limiter.check(&client_key(None, ip), EVERY_METHOD),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Limits how often a client can call each method. Clients are told apart by
+/// their user id when authenticated, and by their IP address otherwise (see
+/// [`layers::client_ip`] for clients behind a proxy).
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new() -> Self {
+        Self {
+            limiter: Arc::new(RateLimiter::from_env()),
+        }
+    }
+
+    /// The limiter shared by the services of this layer, for callers that
+    /// don't go through it (e.g. the REST gateway).
+    pub fn limiter(&self) -> Arc<RateLimiter> {
+        self.limiter.clone()
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S> Service<hyper::Request<Body>> for RateLimit<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        // See `RequestLogger` for why the inner service is swapped out
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            // CORS preflights don't count against the quota of the RPC
+            if req.method() == hyper::Method::OPTIONS {
+                return inner.call(req).await;
+            }
+
+            let ip = layers::client_ip(req.headers(), req.extensions());
+            let client = client_key(req.extensions().get::<AuthenticatedUser>(), ip);
+
+            if let Err(status) = limiter.check(&client, req.uri().path()) {
+                return Ok(status.to_http());
+            }
+
+            inner.call(req).await
+        })
+    }
+}
+
+/// Applies [`IpRateLimiter`] to every request, to be added before
+/// `AuthLayer`.
+#[derive(Clone)]
+pub struct IpRateLimitLayer {
+    limiter: IpRateLimiter,
+}
+
+impl IpRateLimitLayer {
+    pub fn new() -> Self {
+        Self {
+            limiter: IpRateLimiter::from_env(),
+        }
+    }
+
+    /// The limiter of this layer, for callers that don't go through it (e.g.
+    /// the REST gateway).
+    pub fn limiter(&self) -> IpRateLimiter {
+        self.limiter.clone()
+    }
+}
+
+impl<S> Layer<S> for IpRateLimitLayer {
+    type Service = IpRateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpRateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IpRateLimit<S> {
+    inner: S,
+    limiter: IpRateLimiter,
+}
+
+impl<S> Service<hyper::Request<Body>> for IpRateLimit<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        // See `RequestLogger` for why the inner service is swapped out
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            let ip = layers::client_ip(req.headers(), req.extensions());
+
+            if let Err(status) = limiter.check(ip) {
+                return Ok(status.to_http());
+            }
+
+            inner.call(req).await
+        })
+    }
+}
@@ -0,0 +1,156 @@
+//! Prometheus metrics, served as text on `/metrics` at `METRICS_ADDRESS`
+//! when it is set.
+
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, Encoder, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use tracing::{info, warn};
+
+use crate::starknet::confirmations;
+use crate::supabase::service_request::ServiceRequestClient;
+
+const DEFAULT_REFRESH_SECS: u64 = 60;
+
+pub static RPC_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "rpc_requests_total",
+        "RPCs handled, by method and gRPC status code",
+        &["method", "code"]
+    )
+    .unwrap()
+});
+
+pub static RPC_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "rpc_duration_seconds",
+        "Time until the response headers of an RPC were ready",
+        &["method"]
+    )
+    .unwrap()
+});
+
+pub static SUPABASE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "supabase_rpc_duration_seconds",
+        "Duration of Supabase RPC calls, by function",
+        &["function"]
+    )
+    .unwrap()
+});
+
+pub static SUPABASE_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "supabase_rpc_errors_total",
+        "Failed Supabase RPC calls, by function and kind of error",
+        &["function", "kind"]
+    )
+    .unwrap()
+});
+
+pub static SUPABASE_CACHE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "supabase_cache_lookups_total",
+        "Lookups in the Supabase read caches, by cache and whether they hit",
+        &["cache", "result"]
+    )
+    .unwrap()
+});
+
+pub static STARKNET_COMMITMENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "starknet_commitments_total",
+        "Commitments submitted to BudiCore, by kind and outcome",
+        &["kind", "outcome"]
+    )
+    .unwrap()
+});
+
+static PENDING_REQUESTS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "service_requests_pending",
+        "Service requests waiting for a provider"
+    )
+    .unwrap()
+});
+
+static COMMITMENTS_AWAITING: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "starknet_commitments_awaiting_confirmation",
+        "Commitments submitted but not yet accepted or rejected"
+    )
+    .unwrap()
+});
+
+/// Starts the metrics endpoint and the task refreshing the gauges, unless
+/// `METRICS_ADDRESS` is unset.
+pub fn start() {
+    let addr = match dotenv::var("METRICS_ADDRESS") {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+
+    let addr: SocketAddr = addr
+        .parse()
+        .expect("UNABLE TO PARSE METRICS ADDRESS STRING");
+
+    let refresh = dotenv::var("METRICS_REFRESH_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_REFRESH_SECS);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(refresh));
+        let requests = ServiceRequestClient::new();
+
+        loop {
+            ticker.tick().await;
+
+            match requests.count_pending().await {
+                Ok(count) => PENDING_REQUESTS.set(count as i64),
+                Err(e) => warn!("unable to count pending requests error={e}"),
+            }
+
+            COMMITMENTS_AWAITING.set(confirmations::refresh().await as i64);
+        }
+    });
+
+    tokio::spawn(async move {
+        let make_svc = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+
+        info!("Serving metrics on {}", addr);
+
+        if let Err(e) = Server::bind(&addr).serve(make_svc).await {
+            warn!("metrics server stopped error={e}");
+        }
+    });
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        let mut res = Response::new(Body::empty());
+        *res.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(res);
+    }
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        warn!("unable to encode metrics error={e}");
+    }
+
+    let mut res = Response::new(Body::from(buffer));
+    if let Ok(value) = encoder.format_type().parse() {
+        res.headers_mut().insert(hyper::header::CONTENT_TYPE, value);
+    }
+
+    Ok(res)
+}
@@ -1 +1,3 @@
 tonic::include_proto!("proto");
+
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("timebank_descriptor");
@@ -0,0 +1,87 @@
+use time::Duration;
+use tracing::warn;
+
+use crate::events::{self, EventKind, ServiceRequestEvent};
+use crate::scheduler::series::today;
+use crate::supabase::{
+    service_request::{RequestState, ServiceRequestClient},
+    user::{HoldRelease, UserClient},
+};
+
+const DEFAULT_NO_SHOW_GRACE_DAYS: i64 = 1;
+
+/// Expires pending requests whose date has passed, rejects applicants that
+/// were not selected, and flags accepted requests that were never started.
+/// The credit hold of a no-show is refunded to the requestor.
+///
+/// The events of each transition are recorded by the RPC that applies it, so
+/// they are only published from here.
+pub(super) async fn run() {
+    let client = ServiceRequestClient::new();
+    let mut transitions = Vec::new();
+
+    match client.expire_pending(today().to_string()).await {
+        Ok(expired) => transitions.extend(expired.into_iter().map(|request| ServiceRequestEvent {
+            request_id: request.id,
+            kind: EventKind::Expired,
+            from_state: Some(RequestState::Pending as i32),
+            to_state: Some(RequestState::Expired as i32),
+            user_id: None,
+        })),
+        Err(e) => warn!("unable to expire pending requests error={e}"),
+    }
+
+    match client.reject_unselected_applicants().await {
+        Ok(rejected) => {
+            transitions.extend(rejected.into_iter().map(|application| ServiceRequestEvent {
+                request_id: application.request_id,
+                kind: EventKind::ApplicationRejected,
+                from_state: None,
+                to_state: None,
+                user_id: Some(application.provider),
+            }))
+        }
+        Err(e) => warn!("unable to reject unselected applicants error={e}"),
+    }
+
+    let grace = dotenv::var("NO_SHOW_GRACE_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_NO_SHOW_GRACE_DAYS);
+
+    match client
+        .flag_no_shows((today() - Duration::days(grace)).to_string())
+        .await
+    {
+        Ok(flagged) => transitions.extend(flagged.into_iter().map(|request| ServiceRequestEvent {
+            user_id: request.provider.clone(),
+            request_id: request.id,
+            kind: EventKind::NoShow,
+            from_state: Some(RequestState::Accepted as i32),
+            to_state: Some(RequestState::NoShow as i32),
+        })),
+        Err(e) => warn!("unable to flag no-shows error={e}"),
+    }
+
+    if transitions.is_empty() {
+        return;
+    }
+
+    let users = UserClient::new();
+
+    for event in transitions.iter().filter(|e| e.kind == EventKind::NoShow) {
+        if let Err(e) = users
+            .release_credit_hold(&event.request_id, HoldRelease::Refunded)
+            .await
+        {
+            warn!(
+                "unable to refund hold for request_id={}, it will be retried on the next run error={e}",
+                event.request_id
+            );
+        }
+    }
+
+    for event in transitions {
+        events::publish(event);
+    }
+}
@@ -0,0 +1,32 @@
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+use tracing::{info, warn};
+
+use crate::supabase::user::UserClient;
+
+const DEFAULT_HOLD_GRACE_MINUTES: i64 = 10;
+
+/// Releases the credit holds that are still open although their request was
+/// completed, or will not go ahead. Holds are released right after the state
+/// change, so this only catches the releases that failed there.
+pub(super) async fn run() {
+    let grace = dotenv::var("HOLD_GRACE_MINUTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_HOLD_GRACE_MINUTES);
+
+    let placed_before = (OffsetDateTime::now_utc() - Duration::minutes(grace))
+        .format(&Rfc3339)
+        .unwrap_or_default();
+
+    match UserClient::new().release_stale_holds(placed_before).await {
+        Ok(released) => {
+            for hold in released {
+                info!(
+                    "released stale hold of {} for request_id={}",
+                    hold.amount, hold.request_id
+                );
+            }
+        }
+        Err(e) => warn!("unable to release stale holds error={e}"),
+    }
+}
@@ -0,0 +1,36 @@
+//! Background jobs that run alongside the gRPC server.
+
+mod expiry;
+mod holds;
+pub mod rrule;
+pub mod series;
+
+use std::time::Duration;
+
+use tracing::info;
+
+const DEFAULT_INTERVAL_SECS: u64 = 300;
+
+/// Spawns the scheduler loop. Every job runs once per
+/// `SCHEDULER_INTERVAL_SECS`, one after the other.
+pub fn start() {
+    // an interval of 0 would make `tokio::time::interval` panic
+    let interval = dotenv::var("SCHEDULER_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|s| *s > 0)
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+    info!("scheduler running every {interval}s");
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+
+        loop {
+            ticker.tick().await;
+            series::run().await;
+            expiry::run().await;
+            holds::run().await;
+        }
+    });
+}
@@ -0,0 +1,435 @@
+//! A subset of the RFC 5545 recurrence rule used for recurring service
+//! requests.
+//!
+//! Supported parts are `FREQ` (`DAILY`, `WEEKLY` or `MONTHLY`), `INTERVAL`,
+//! `COUNT`, `UNTIL` and, for weekly rules, `BYDAY`. `INTERVAL` is at most a
+//! year's worth of periods. Occurrences are dates; the time of day is kept in
+//! the request itself.
+
+use core::fmt;
+use std::str::FromStr;
+
+use time::{macros::format_description, Date, Duration, Month, Weekday};
+
+// guards against rules that would never produce a date in range
+const MAX_ITERATIONS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Frequency {
+    /// The largest `INTERVAL` accepted, a year's worth of periods.
+    fn max_interval(self) -> u32 {
+        match self {
+            Frequency::Daily => 366,
+            Frequency::Weekly => 52,
+            Frequency::Monthly => 12,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<Date>,
+    pub by_day: Vec<Weekday>,
+}
+
+#[derive(Debug)]
+pub enum RuleError {
+    MissingFrequency,
+    Unsupported(String),
+    InvalidValue(String),
+}
+
+impl std::error::Error for RuleError {}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::MissingFrequency => write!(f, "recurrence rule is missing FREQ"),
+            RuleError::Unsupported(s) => write!(f, "unsupported recurrence rule part : {s}"),
+            RuleError::InvalidValue(s) => write!(f, "invalid recurrence rule value : {s}"),
+        }
+    }
+}
+
+impl FromStr for RecurrenceRule {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("RRULE:").unwrap_or(s);
+
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        let mut by_day = Vec::new();
+
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| RuleError::InvalidValue(part.to_owned()))?;
+
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        _ => return Err(RuleError::Unsupported(part.to_owned())),
+                    })
+                }
+
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|i| *i > 0)
+                        .ok_or_else(|| RuleError::InvalidValue(part.to_owned()))?
+                }
+
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse()
+                            .map_err(|_| RuleError::InvalidValue(part.to_owned()))?,
+                    )
+                }
+
+                "UNTIL" => {
+                    // both the DATE and the DATE-TIME forms start with YYYYMMDD
+                    let date = value
+                        .get(..8)
+                        .and_then(|d| {
+                            Date::parse(d, format_description!("[year][month][day]")).ok()
+                        })
+                        .ok_or_else(|| RuleError::InvalidValue(part.to_owned()))?;
+
+                    until = Some(date)
+                }
+
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        by_day.push(
+                            parse_weekday(day)
+                                .ok_or_else(|| RuleError::InvalidValue(part.to_owned()))?,
+                        );
+                    }
+                }
+
+                _ => return Err(RuleError::Unsupported(part.to_owned())),
+            }
+        }
+
+        let frequency = frequency.ok_or(RuleError::MissingFrequency)?;
+
+        if interval > frequency.max_interval() {
+            return Err(RuleError::InvalidValue(format!(
+                "INTERVAL cannot be more than {} for this frequency",
+                frequency.max_interval()
+            )));
+        }
+
+        if !by_day.is_empty() && frequency != Frequency::Weekly {
+            return Err(RuleError::Unsupported(
+                "BYDAY is only supported for weekly rules".to_owned(),
+            ));
+        }
+
+        by_day.sort_by_key(|d| d.number_days_from_monday());
+        by_day.dedup();
+
+        Ok(Self {
+            frequency,
+            interval,
+            count,
+            until,
+            by_day,
+        })
+    }
+}
+
+impl RecurrenceRule {
+    /// Returns the occurrences of the rule, starting at `start`, that fall
+    /// within `from..=to`.
+    pub fn occurrences_between(&self, start: Date, from: Date, to: Date) -> Vec<Date> {
+        let mut dates = Vec::new();
+
+        for (seen, date) in self.iter(start).take(MAX_ITERATIONS).enumerate() {
+            if date > to || self.until.is_some_and(|until| date > until) {
+                break;
+            }
+
+            if self.count.is_some_and(|count| seen >= count as usize) {
+                break;
+            }
+
+            if date >= from {
+                dates.push(date);
+            }
+        }
+
+        dates
+    }
+
+    fn iter(&self, start: Date) -> impl Iterator<Item = Date> + '_ {
+        let interval = self.interval as i64;
+
+        let by_day = if self.by_day.is_empty() {
+            vec![start.weekday()]
+        } else {
+            self.by_day.clone()
+        };
+
+        let week_start = start.checked_sub(Duration::days(
+            start.weekday().number_days_from_monday() as i64,
+        ));
+
+        // ends with the first period past the last date `Date` can hold
+        (0..)
+            .map_while(move |n: i64| -> Option<Vec<Date>> {
+                match self.frequency {
+                    Frequency::Daily => start
+                        .checked_add(Duration::days(n * interval))
+                        .map(|date| vec![date]),
+
+                    Frequency::Weekly => {
+                        let week = week_start?.checked_add(Duration::weeks(n * interval))?;
+
+                        Some(
+                            by_day
+                                .iter()
+                                .filter_map(|day| {
+                                    week.checked_add(Duration::days(
+                                        day.number_days_from_monday() as i64
+                                    ))
+                                })
+                                .filter(|date| *date >= start)
+                                .collect(),
+                        )
+                    }
+
+                    // months without the start day (e.g. the 31st) are skipped
+                    Frequency::Monthly => {
+                        let months = start.month() as i64 - 1 + n * interval;
+                        let year = start.year() as i64 + months / 12;
+                        let month = Month::try_from((months % 12) as u8 + 1).unwrap();
+
+                        if year > Date::MAX.year() as i64 {
+                            return None;
+                        }
+
+                        Some(
+                            Date::from_calendar_date(year as i32, month, start.day())
+                                .into_iter()
+                                .collect(),
+                        )
+                    }
+                }
+            })
+            .flatten()
+    }
+}
+
+fn parse_weekday(s: &str) -> Option<Weekday> {
+    match s.trim().to_ascii_uppercase().as_str() {
+        "MO" => Some(Weekday::Monday),
+        "TU" => Some(Weekday::Tuesday),
+        "WE" => Some(Weekday::Wednesday),
+        "TH" => Some(Weekday::Thursday),
+        "FR" => Some(Weekday::Friday),
+        "SA" => Some(Weekday::Saturday),
+        "SU" => Some(Weekday::Sunday),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::date;
+
+    use super::*;
+
+    fn rule(s: &str) -> RecurrenceRule {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_supported_parts() {
+        let parsed = rule("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=WE,MO,WE;COUNT=5;UNTIL=20230301");
+
+        assert_eq!(
+            parsed,
+            RecurrenceRule {
+                frequency: Frequency::Weekly,
+                interval: 2,
+                count: Some(5),
+                until: Some(date!(2023 - 03 - 01)),
+                by_day: vec![Weekday::Monday, Weekday::Wednesday],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_lowercase_and_date_time_until() {
+        let parsed = rule("freq=daily;until=20230115T120000Z");
+
+        assert_eq!(parsed.frequency, Frequency::Daily);
+        assert_eq!(parsed.interval, 1);
+        assert_eq!(parsed.until, Some(date!(2023 - 01 - 15)));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for s in [
+            "",
+            "INTERVAL=2",
+            "FREQ=YEARLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;INTERVAL=two",
+            "FREQ=DAILY;COUNT=-1",
+            "FREQ=DAILY;UNTIL=2023",
+            "FREQ=WEEKLY;BYDAY=XX",
+            "FREQ=DAILY;BYDAY=MO",
+            "FREQ=MONTHLY;BYMONTHDAY=1",
+            "FREQ=DAILY;INTERVAL",
+            "FREQ=DAILY;INTERVAL=367",
+            "FREQ=WEEKLY;INTERVAL=53",
+            "FREQ=MONTHLY;INTERVAL=13",
+            "FREQ=WEEKLY;INTERVAL=4000000000",
+            "FREQ=DAILY;INTERVAL=99999999999",
+        ] {
+            assert!(s.parse::<RecurrenceRule>().is_err(), "`{s}` was accepted");
+        }
+    }
+
+    #[test]
+    fn stops_at_the_last_representable_date() {
+        let start = Date::MAX - Duration::days(400);
+
+        for s in [
+            "FREQ=DAILY;INTERVAL=366",
+            "FREQ=WEEKLY;INTERVAL=52;BYDAY=MO,SU",
+            "FREQ=MONTHLY;INTERVAL=12",
+        ] {
+            let dates = rule(s).occurrences_between(start, start, Date::MAX);
+
+            assert!(!dates.is_empty(), "`{s}` had no occurrence");
+            assert!(dates.len() <= 4, "`{s}` had {} occurrences", dates.len());
+        }
+    }
+
+    #[test]
+    fn daily_with_interval() {
+        let start = date!(2023 - 01 - 01);
+
+        assert_eq!(
+            rule("FREQ=DAILY;INTERVAL=3").occurrences_between(start, start, date!(2023 - 01 - 10)),
+            vec![
+                date!(2023 - 01 - 01),
+                date!(2023 - 01 - 04),
+                date!(2023 - 01 - 07),
+                date!(2023 - 01 - 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_by_day() {
+        // a Wednesday, so the Monday of the first week is skipped
+        let start = date!(2023 - 01 - 04);
+
+        assert_eq!(
+            rule("FREQ=WEEKLY;BYDAY=MO,WE").occurrences_between(
+                start,
+                start,
+                date!(2023 - 01 - 16)
+            ),
+            vec![
+                date!(2023 - 01 - 04),
+                date!(2023 - 01 - 09),
+                date!(2023 - 01 - 11),
+                date!(2023 - 01 - 16),
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_defaults_to_start_day_and_honours_interval() {
+        let start = date!(2023 - 01 - 04);
+
+        assert_eq!(
+            rule("FREQ=WEEKLY;INTERVAL=2").occurrences_between(start, start, date!(2023 - 02 - 28)),
+            vec![
+                date!(2023 - 01 - 04),
+                date!(2023 - 01 - 18),
+                date!(2023 - 02 - 01),
+                date!(2023 - 02 - 15),
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly_skips_months_without_the_start_day() {
+        let start = date!(2023 - 01 - 31);
+
+        assert_eq!(
+            rule("FREQ=MONTHLY").occurrences_between(start, start, date!(2023 - 06 - 30)),
+            vec![
+                date!(2023 - 01 - 31),
+                date!(2023 - 03 - 31),
+                date!(2023 - 05 - 31),
+            ]
+        );
+    }
+
+    #[test]
+    fn count_includes_occurrences_before_the_range() {
+        let start = date!(2023 - 01 - 01);
+
+        // 1st to 5th, of which only the 4th and 5th are in range
+        assert_eq!(
+            rule("FREQ=DAILY;COUNT=5").occurrences_between(
+                start,
+                date!(2023 - 01 - 04),
+                date!(2023 - 01 - 31)
+            ),
+            vec![date!(2023 - 01 - 04), date!(2023 - 01 - 05)]
+        );
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let start = date!(2023 - 01 - 01);
+
+        assert_eq!(
+            rule("FREQ=DAILY;INTERVAL=2;UNTIL=20230105").occurrences_between(
+                start,
+                start,
+                date!(2023 - 01 - 31)
+            ),
+            vec![
+                date!(2023 - 01 - 01),
+                date!(2023 - 01 - 03),
+                date!(2023 - 01 - 05),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_when_range_is_before_start() {
+        let start = date!(2023 - 02 - 01);
+
+        assert!(rule("FREQ=DAILY")
+            .occurrences_between(start, date!(2023 - 01 - 01), date!(2023 - 01 - 31))
+            .is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashSet;
+
+use serde_json::json;
+use time::{macros::format_description, Date, Duration, OffsetDateTime};
+use tracing::{info, warn};
+
+use crate::proto::servicerequest::{create::NewServiceRequestData, ServiceRequestData};
+use crate::scheduler::rrule::RecurrenceRule;
+use crate::supabase::{
+    organisation::OrganisationClient,
+    series::{Series, SeriesClient},
+    ClientError, InternalErrorKind,
+};
+
+const DEFAULT_HORIZON_DAYS: i64 = 14;
+
+/// How far ahead occurrences are created.
+pub fn horizon() -> Duration {
+    let days = dotenv::var("RECURRENCE_HORIZON_DAYS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_HORIZON_DAYS);
+
+    Duration::days(days)
+}
+
+pub fn today() -> Date {
+    OffsetDateTime::now_utc().date()
+}
+
+pub fn parse_date(value: &str) -> Option<Date> {
+    // accepts both plain dates and timestamps
+    value
+        .get(..10)
+        .and_then(|d| Date::parse(d, format_description!("[year]-[month]-[day]")).ok())
+}
+
+/// Creates the occurrences of `series` up to `until` that don't exist yet,
+/// as ordinary service requests, and returns them.
+///
+/// Occurrences are inserted by `series_createoccurrence` rather than through
+/// the `Create` RPC, so the checks of `Create` are repeated here: the
+/// requestor of a series made on behalf of an organisation must still be
+/// allowed to spend its credits, or no occurrence is created.
+pub async fn materialise(
+    series: &Series,
+    series_client: &SeriesClient,
+    until: Date,
+) -> Result<Vec<ServiceRequestData>, ClientError> {
+    let parsing_error = |s: String| ClientError::InternalError(InternalErrorKind::ParsingError(s));
+
+    let rule = series
+        .recurrence
+        .parse::<RecurrenceRule>()
+        .map_err(|e| parsing_error(e.to_string()))?;
+
+    let starts_on = parse_date(&series.starts_on)
+        .ok_or_else(|| parsing_error(format!("invalid series start {}", series.starts_on)))?;
+
+    let from = series
+        .materialized_until
+        .as_deref()
+        .and_then(parse_date)
+        .map(|d| d + Duration::days(1))
+        .unwrap_or(starts_on)
+        .max(today());
+
+    let existing = series_client
+        .get_occurrence_dates(&series.id, from.to_string())
+        .await?
+        .iter()
+        .filter_map(|d| parse_date(d))
+        .collect::<HashSet<_>>();
+
+    let organisation_id = series
+        .template
+        .get("organisation_id")
+        .and_then(|id| id.as_str())
+        .filter(|id| !id.is_empty());
+
+    if let Some(organisation_id) = organisation_id {
+        let can_spend = OrganisationClient::new()
+            .can_spend(organisation_id, &series.requestor)
+            .await?;
+
+        if !can_spend {
+            warn!(
+                "requestor of series_id={} can no longer spend the credits of organisation_id={organisation_id}, skipping",
+                series.id
+            );
+            return Ok(Vec::new());
+        }
+    }
+
+    let mut created = Vec::new();
+
+    for date in rule.occurrences_between(starts_on, from, until) {
+        if existing.contains(&date) {
+            continue;
+        }
+
+        let mut data = series.template.clone();
+        data["date"] = json!(date.to_string());
+
+        let data = serde_json::from_value::<NewServiceRequestData>(data)
+            .map_err(|e| parsing_error(e.to_string()))?;
+
+        let request = series_client
+            .create_occurrence(&series.id, &series.requestor, data)
+            .await?;
+
+        created.push(request);
+    }
+
+    series_client
+        .update(
+            &series.id,
+            json!({ "materialized_until": until.to_string() }),
+        )
+        .await?;
+
+    Ok(created)
+}
+
+pub(super) async fn run() {
+    let series_client = SeriesClient::new();
+    let until = today() + horizon();
+
+    let active = match series_client.get_active().await {
+        Ok(values) => values,
+        Err(e) => {
+            warn!("unable to fetch recurring series error={e}");
+            return;
+        }
+    };
+
+    for series in active {
+        match materialise(&series, &series_client, until).await {
+            Ok(created) if !created.is_empty() => info!(
+                "created {} occurrence(s) for series_id={}",
+                created.len(),
+                series.id
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("unable to materialise series_id={} error={e}", series.id),
+        }
+    }
+}
@@ -1,32 +1,46 @@
-// TODO:
-// 1. create auth middleware that checks for valid jwt
-//    - how it'd work is by retrieving jwt attached in request metadata,
-//      and get the user associated with the token. the append the user id
-//      in the request metadata.
-//
+mod events;
+mod gateway;
+mod health;
 mod layers;
+mod metrics;
 mod proto;
+mod scheduler;
 mod services;
 mod starknet;
 mod supabase;
+mod telemetry;
+mod tls;
 
 use std::{
     process::exit,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use color_eyre::Report;
 use dotenv::dotenv;
-use layers::logger::RequestLoggerLayer;
+use layers::{
+    auth::AuthLayer,
+    idempotency::IdempotencyLayer,
+    logger::RequestLoggerLayer,
+    message_size::MessageSizeLayer,
+    metrics::MetricsLayer,
+    policy::PolicyLayer,
+    rate_limit::{IpRateLimitLayer, RateLimitLayer},
+};
 use services::{
+    admin::{AdminServer, AdminService},
     auth::{AuthServer, AuthService},
+    organisation::{OrganisationServer, OrganisationService},
     rating::{RatingServer, RatingService},
     service_request::{ServiceRequestServer, ServiceRequestService},
     user::{UserServer, UserService},
 };
-use tonic::transport::Server;
+use tonic::{codec::CompressionEncoding, server::NamedService, transport::Server};
 use tracing::info;
-use tracing_subscriber::{fmt::time::LocalTime, EnvFilter};
+use tracing_subscriber::{
+    fmt::time::LocalTime, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
 
 fn register_shutdown_handler() {
     let shutdown_flag = Arc::new(Mutex::new(false));
@@ -39,6 +53,7 @@ fn register_shutdown_handler() {
             *flag = true;
             info!("press CTRL-C again to exit...");
         } else {
+            telemetry::shutdown();
             exit(0)
         }
     })
@@ -52,14 +67,82 @@ fn setup() {
         std::env::set_var("RUST_LOG", "info")
     }
 
-    tracing_subscriber::fmt::fmt()
-        .with_timer(LocalTime::rfc_3339())
-        .with_env_filter(EnvFilter::from_default_env())
+    // LOG_FORMAT=json outputs one JSON object per line, with the fields of
+    // the current RPC span
+    let fmt = match dotenv::var("LOG_FORMAT").as_deref() {
+        Ok("json") => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_timer(LocalTime::rfc_3339())
+            .boxed(),
+        _ => tracing_subscriber::fmt::layer()
+            .with_timer(LocalTime::rfc_3339())
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt)
+        .with(telemetry::layer())
         .init();
 
     register_shutdown_handler();
 }
 
+/// The services whose responses are compressed unless GRPC_COMPRESSION is
+/// set, as they can return long lists.
+const DEFAULT_COMPRESSED_SERVICES: &[&str] = &["servicerequest.ServiceRequest", "user.User"];
+
+// GRPC_COMPRESSION is a comma separated list of the services whose
+// responses are gzip compressed (for the clients that accept it), `*` for
+// every service or `none`.
+fn uses_compression<S: NamedService>(_: &S) -> bool {
+    match dotenv::var("GRPC_COMPRESSION") {
+        Ok(services) => services.trim() == "*" || services.split(',').any(|s| s.trim() == S::NAME),
+        Err(_) => DEFAULT_COMPRESSED_SERVICES.contains(&S::NAME),
+    }
+}
+
+/// Applies the compression settings of [`uses_compression`] to a service.
+/// Requests aren't decompressed, so that the message size limits hold.
+macro_rules! with_compression {
+    ($server:expr) => {{
+        let server = $server;
+
+        if uses_compression(&server) {
+            server.send_compressed(CompressionEncoding::Gzip)
+        } else {
+            server
+        }
+    }};
+}
+
+// Lets browsers call the services over gRPC-Web. CORS_ALLOWED_ORIGINS is a
+// comma separated list of origins, all origins are allowed when it is unset
+// or `*`.
+fn grpc_web() -> tonic_web::Config {
+    let config = match dotenv::var("CORS_ALLOWED_ORIGINS").as_deref() {
+        Ok(origins) if origins.trim() != "*" => tonic_web::config().allow_origins(
+            origins
+                .split(',')
+                .map(str::trim)
+                .filter(|o| !o.is_empty())
+                .map(str::to_owned)
+                .collect::<Vec<_>>(),
+        ),
+        _ => tonic_web::config().allow_all_origins(),
+    };
+
+    let max_age = dotenv::var("CORS_MAX_AGE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60 * 60);
+
+    config
+        .max_age(Duration::from_secs(max_age))
+        .expose_headers(vec![layers::logger::REQUEST_ID_HEADER, "retry-after"])
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Report> {
     setup();
@@ -69,16 +152,96 @@ async fn main() -> Result<(), Report> {
         .parse()
         .expect("UNABLE TO PARSE SOKCET ADDRESS STRING");
 
-    info!("Listening on {}", addr);
+    scheduler::start();
+    metrics::start();
+
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    health::start(
+        health_reporter,
+        vec![
+            ServiceRequestServer::<ServiceRequestService>::NAME,
+            RatingServer::<RatingService>::NAME,
+            UserServer::<UserService>::NAME,
+            AuthServer::<AuthService>::NAME,
+            OrganisationServer::<OrganisationService>::NAME,
+            AdminServer::<AdminService>::NAME,
+        ],
+    );
+
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(
+            tonic_health::proto::GRPC_HEALTH_V1_FILE_DESCRIPTOR_SET,
+        )
+        .build()
+        .expect("UNABLE TO BUILD REFLECTION SERVICE");
+
+    let service_requests = Arc::new(ServiceRequestService::new());
+    let ratings = Arc::new(RatingService::new());
+    let users = Arc::new(UserService::new());
+    let auth = Arc::new(AuthService::new());
+    let organisations = Arc::new(OrganisationService::new());
+    let admin = Arc::new(AdminService::new());
+
+    let ip_rate_limit = IpRateLimitLayer::new();
+    let rate_limit = RateLimitLayer::new();
+    let idempotency = IdempotencyLayer::new();
 
-    Server::builder()
+    gateway::start(
+        gateway::Services {
+            service_requests: service_requests.clone(),
+            ratings: ratings.clone(),
+            users: users.clone(),
+            auth: auth.clone(),
+            organisations: organisations.clone(),
+            admin: admin.clone(),
+        },
+        ip_rate_limit.limiter(),
+        rate_limit.limiter(),
+        idempotency.store(),
+    );
+
+    let grpc_web = grpc_web();
+
+    let server = Server::builder()
+        .accept_http1(true)
         .layer(RequestLoggerLayer::default())
-        .add_service(ServiceRequestServer::new(ServiceRequestService::new()))
-        .add_service(RatingServer::new(RatingService::new()))
-        .add_service(UserServer::new(UserService::new()))
-        .add_service(AuthServer::new(AuthService::new()))
-        .serve(addr)
-        .await?;
+        .layer(MetricsLayer::default())
+        .layer(MessageSizeLayer::new())
+        .layer(ip_rate_limit)
+        .layer(AuthLayer::new())
+        .layer(PolicyLayer::default())
+        .layer(rate_limit)
+        .layer(idempotency)
+        .add_service(
+            grpc_web.enable(with_compression!(ServiceRequestServer::from_arc(
+                service_requests
+            ))),
+        )
+        .add_service(grpc_web.enable(with_compression!(RatingServer::from_arc(ratings))))
+        .add_service(grpc_web.enable(with_compression!(UserServer::from_arc(users))))
+        .add_service(grpc_web.enable(with_compression!(AuthServer::from_arc(auth))))
+        .add_service(
+            grpc_web.enable(with_compression!(OrganisationServer::from_arc(
+                organisations
+            ))),
+        )
+        .add_service(grpc_web.enable(with_compression!(AdminServer::from_arc(admin))))
+        .add_service(health_service)
+        .add_service(reflection_service);
+
+    match tls::TlsConfig::from_env() {
+        Some(config) => {
+            info!("Listening on {} over TLS", addr);
+            server
+                .serve_with_incoming(tls::incoming(addr, config).await?)
+                .await?;
+        }
+        None => {
+            info!("Listening on {}", addr);
+            server.serve(addr).await?;
+        }
+    }
 
     Ok(())
 }
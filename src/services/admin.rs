@@ -0,0 +1,320 @@
+pub use crate::proto::admin::admin_server::AdminServer;
+
+use crate::layers::auth::{AuthenticatedUser, Role};
+use crate::proto::admin::{
+    adjust_credits, admin_server::Admin, force_cancel, get_moderation_queue, list_flagged,
+    resolve_report, suspend_user, unsuspend_user,
+};
+use crate::proto::moderation::{ModerationAction, ReportStatus};
+use crate::services::Result;
+use crate::supabase::{
+    admin::AdminClient,
+    auth::AuthClient,
+    moderation::ModerationClient,
+    service_request::{RequestState, ServiceRequestClient},
+    user::{HoldRelease, UserClient},
+    ClientError,
+};
+
+use tonic::{Request, Response, Status};
+use tracing::warn;
+
+/// GoTrue has no permanent ban, so an indefinite suspension is a very long one.
+const INDEFINITE_BAN: &str = "876000h";
+
+pub struct AdminService {
+    client: AdminClient,
+    auth: AuthClient,
+    moderation: ModerationClient,
+    requests: ServiceRequestClient,
+    users: UserClient,
+}
+
+impl AdminService {
+    pub fn new() -> Self {
+        Self {
+            client: AdminClient::new(),
+            auth: AuthClient::new(),
+            moderation: ModerationClient::new(),
+            requests: ServiceRequestClient::new(),
+            users: UserClient::new(),
+        }
+    }
+
+    async fn record(&self, actor: &str, action: &str, target: &str, reason: &str) {
+        if let Err(e) = self
+            .client
+            .record_action(actor, action, target, reason)
+            .await
+        {
+            warn!("error when recording admin action={action} target={target} error={e}");
+        }
+    }
+}
+
+fn ban_duration(hours: u32) -> String {
+    match hours {
+        0 => INDEFINITE_BAN.to_owned(),
+        hours => format!("{hours}h"),
+    }
+}
+
+fn check_reason(reason: &str) -> Result<()> {
+    if reason.trim().is_empty() {
+        return Err(Status::invalid_argument("a reason is required"));
+    }
+
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn suspend_user(
+        &self,
+        request: Request<suspend_user::Request>,
+    ) -> Result<Response<suspend_user::Response>> {
+        let actor = AuthenticatedUser::with_role(&request, Role::Admin)?
+            .id
+            .clone();
+
+        let suspend_user::Request {
+            user_id,
+            duration_hours,
+            reason,
+        } = request.into_inner();
+
+        check_reason(&reason)?;
+
+        if user_id == actor {
+            return Err(Status::invalid_argument("cannot suspend yourself"));
+        }
+
+        match self
+            .auth
+            .ban_user(&user_id, &ban_duration(duration_hours))
+            .await
+        {
+            Ok(()) => {
+                self.record(&actor, "suspend_user", &user_id, &reason).await;
+                Ok(Response::new(suspend_user::Response {}))
+            }
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    async fn unsuspend_user(
+        &self,
+        request: Request<unsuspend_user::Request>,
+    ) -> Result<Response<unsuspend_user::Response>> {
+        let actor = AuthenticatedUser::with_role(&request, Role::Admin)?
+            .id
+            .clone();
+
+        let unsuspend_user::Request { user_id, reason } = request.into_inner();
+
+        check_reason(&reason)?;
+
+        match self.auth.ban_user(&user_id, "none").await {
+            Ok(()) => {
+                self.record(&actor, "unsuspend_user", &user_id, &reason)
+                    .await;
+                Ok(Response::new(unsuspend_user::Response {}))
+            }
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. the request is not completed or cancelled already
+    // 2. a hold placed for the request is refunded to the requestor
+    async fn force_cancel(
+        &self,
+        request: Request<force_cancel::Request>,
+    ) -> Result<Response<force_cancel::Response>> {
+        let actor = AuthenticatedUser::with_role(&request, Role::Admin)?
+            .id
+            .clone();
+
+        let force_cancel::Request { request_id, reason } = request.into_inner();
+
+        check_reason(&reason)?;
+
+        let current = match self.requests.get_including_hidden("id", &request_id).await {
+            Ok(values) => values
+                .into_iter()
+                .next()
+                .ok_or_else(|| Status::not_found("service request not found"))?,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        let state = RequestState::of(&current);
+
+        if matches!(
+            state,
+            Some(RequestState::Completed) | Some(RequestState::Cancelled)
+        ) {
+            return Err(Status::failed_precondition(
+                "the service request is already closed",
+            ));
+        }
+
+        let res = self.client.force_cancel(&request_id, &actor, &reason).await;
+
+        match res {
+            Ok(value) => {
+                if matches!(
+                    state,
+                    Some(RequestState::Accepted) | Some(RequestState::Ongoing)
+                ) {
+                    if let Err(e) = self
+                        .users
+                        .release_credit_hold(&request_id, HoldRelease::Refunded)
+                        .await
+                    {
+                        warn!("error when refunding hold for request_id={request_id}, the scheduler will retry error={e}");
+                    }
+                }
+
+                Ok(Response::new(force_cancel::Response {
+                    request: Some(value),
+                }))
+            }
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    async fn adjust_credits(
+        &self,
+        request: Request<adjust_credits::Request>,
+    ) -> Result<Response<adjust_credits::Response>> {
+        let actor = AuthenticatedUser::with_role(&request, Role::Admin)?
+            .id
+            .clone();
+
+        let adjust_credits::Request {
+            user_id,
+            amount,
+            reason,
+        } = request.into_inner();
+
+        check_reason(&reason)?;
+
+        if !amount.is_finite() || amount == 0.0 {
+            return Err(Status::invalid_argument("amount must be a non-zero number"));
+        }
+
+        let res = self
+            .client
+            .adjust_credits(user_id, amount, actor, reason)
+            .await;
+
+        match res {
+            Ok(value) => Ok(Response::new(adjust_credits::Response {
+                transaction: Some(value),
+            })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    async fn list_flagged(
+        &self,
+        request: Request<list_flagged::Request>,
+    ) -> Result<Response<list_flagged::Response>> {
+        AuthenticatedUser::with_role(&request, Role::Moderator)?;
+
+        let res = self.client.get_flagged().await;
+
+        match res {
+            Ok(values) => Ok(Response::new(list_flagged::Response { items: values })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    async fn get_moderation_queue(
+        &self,
+        request: Request<get_moderation_queue::Request>,
+    ) -> Result<Response<get_moderation_queue::Response>> {
+        AuthenticatedUser::with_role(&request, Role::Moderator)?;
+
+        let res = self.moderation.get_queue().await;
+
+        match res {
+            Ok(values) => Ok(Response::new(get_moderation_queue::Response {
+                reports: values,
+            })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. the report is still open
+    // 2. a moderator can't suspend themselves
+    async fn resolve_report(
+        &self,
+        request: Request<resolve_report::Request>,
+    ) -> Result<Response<resolve_report::Response>> {
+        let moderator = AuthenticatedUser::with_role(&request, Role::Moderator)?
+            .id
+            .clone();
+
+        let resolve_report::Request {
+            report_id,
+            action,
+            note,
+            suspend_hours,
+        } = request.into_inner();
+
+        let action = ModerationAction::from_i32(action)
+            .ok_or_else(|| Status::invalid_argument("invalid moderation action"))?;
+
+        let report = match self.moderation.get_by_id(&report_id).await {
+            Ok(Some(value)) => value,
+            Ok(None) => return Err(Status::not_found("report not found")),
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        if report.status != ReportStatus::Open as i32 {
+            return Err(Status::failed_precondition(
+                "the report is already resolved",
+            ));
+        }
+
+        if action == ModerationAction::Suspend {
+            if report.target_owner == moderator {
+                return Err(Status::invalid_argument("cannot suspend yourself"));
+            }
+
+            let res = self
+                .auth
+                .ban_user(&report.target_owner, &ban_duration(suspend_hours))
+                .await;
+
+            match res {
+                Ok(()) => {}
+                Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+                Err(ClientError::InternalError(e)) => return Err(e.into()),
+            }
+        }
+
+        let res = self
+            .moderation
+            .resolve_report(report_id, moderator, action, note)
+            .await;
+
+        match res {
+            Ok(value) => Ok(Response::new(resolve_report::Response {
+                report: Some(value),
+            })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+}
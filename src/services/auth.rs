@@ -63,7 +63,7 @@ impl Auth for AuthService {
                 match res {
                     Ok(_) => Ok(Response::new(sign_up::Response { user_id: user.id })),
                     Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-                    Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+                    Err(ClientError::InternalError(e)) => Err(e.into()),
                 }
             }
 
@@ -0,0 +1,331 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tonic::Status;
+
+use crate::proto::google::protobuf::FieldMask;
+use crate::services::{error_messages, Result};
+
+/// The most an update can write, counted over the paths of the mask and the
+/// values sent for them, so that a mask can't be used to write oversized
+/// values (e.g. a long string in a JSON column).
+const MAX_PATCH_SIZE: usize = 16 * 1024;
+
+/// Builds the JSON body for a partial update from a typed message and the
+/// `update_mask` sent along with it.
+///
+/// Only the columns named in the mask are included. A path that reaches into
+/// a JSON column (e.g. `location.name`) is merged into the column's current
+/// value so the rest of the column is kept as is. Paths whose column is in
+/// `protected` are rejected, and so are overlapping paths (e.g. `location`
+/// and `location.name`) and values adding up to more than `MAX_PATCH_SIZE`.
+///
+/// `current` is read before the update is sent, so the update must only be
+/// applied while the columns of [`merged_columns`] are unchanged, or it could
+/// undo a concurrent update of another key of the same column.
+pub fn build_patch<T: Serialize>(
+    data: &T,
+    mask: Option<&FieldMask>,
+    current: &Value,
+    protected: &[&str],
+) -> Result<Value> {
+    let paths = match mask {
+        Some(mask) if !mask.paths.is_empty() => &mask.paths,
+        _ => return Err(Status::invalid_argument("update mask cannot be empty")),
+    };
+
+    if let Some((path, other)) = overlapping(paths) {
+        return Err(Status::invalid_argument(format!(
+            "update mask paths `{path}` and `{other}` overlap"
+        )));
+    }
+
+    let data = serde_json::to_value(data).map_err(|e| Status::internal(e.to_string()))?;
+    let mut patch = Map::new();
+    // only what the caller sent counts, not the current value it is merged in
+    let mut size = 0;
+
+    for path in paths {
+        let segments = path.split('.').collect::<Vec<_>>();
+        let column = segments[0];
+
+        if segments.iter().any(|s| s.is_empty()) {
+            return Err(Status::invalid_argument(format!(
+                "invalid update mask path `{path}`"
+            )));
+        }
+
+        if protected.contains(&column) {
+            return Err(Status::permission_denied(format!(
+                "field `{column}` cannot be updated"
+            )));
+        }
+
+        let Some(value) = lookup(&data, &segments) else {
+            return Err(Status::invalid_argument(format!("unknown field `{path}`")));
+        };
+
+        size += path.len() + value.to_string().len();
+
+        if size > MAX_PATCH_SIZE {
+            return Err(Status::invalid_argument(format!(
+                "update is larger than {MAX_PATCH_SIZE} bytes"
+            )));
+        }
+
+        if segments.len() == 1 {
+            patch.insert(column.to_owned(), value.clone());
+            continue;
+        }
+
+        let merged = patch
+            .entry(column)
+            .or_insert_with(|| current.get(column).cloned().unwrap_or(Value::Null));
+
+        assign(merged, &segments[1..], value.clone())?;
+    }
+
+    Ok(Value::Object(patch))
+}
+
+/// The current value of each column that [`build_patch`] merges a nested
+/// path into.
+pub fn merged_columns(mask: Option<&FieldMask>, current: &Value) -> Vec<(String, Value)> {
+    let mut columns: Vec<(String, Value)> = Vec::new();
+
+    for path in mask.map(|m| m.paths.as_slice()).unwrap_or_default() {
+        let Some((column, _)) = path.split_once('.') else {
+            continue;
+        };
+
+        if columns.iter().all(|(c, _)| c != column) {
+            let value = current.get(column).cloned().unwrap_or(Value::Null);
+            columns.push((column.to_owned(), value));
+        }
+    }
+
+    columns
+}
+
+/// Returns whether `path` is in the mask, either as is or as the column of a
+/// nested path.
+pub fn contains(mask: Option<&FieldMask>, path: &str) -> bool {
+    mask.map(|m| {
+        m.paths
+            .iter()
+            .any(|p| p == path || p.split('.').next() == Some(path))
+    })
+    .unwrap_or_default()
+}
+
+// The first two paths of which one is inside the other, as which of them is
+// applied last would decide the result. Repeating a path is harmless.
+fn overlapping(paths: &[String]) -> Option<(&str, &str)> {
+    paths.iter().find_map(|path| {
+        paths
+            .iter()
+            .find(|other| {
+                other.len() > path.len()
+                    && other.starts_with(path.as_str())
+                    && other[path.len()..].starts_with('.')
+            })
+            .map(|other| (path.as_str(), other.as_str()))
+    })
+}
+
+fn lookup<'a>(value: &'a Value, segments: &[&str]) -> Option<&'a Value> {
+    segments
+        .iter()
+        .try_fold(value, |value, segment| value.get(segment))
+}
+
+fn assign(target: &mut Value, segments: &[&str], value: Value) -> Result<()> {
+    if target.is_null() {
+        *target = Value::Object(Map::new());
+    }
+
+    let Some(object) = target.as_object_mut() else {
+        return Err(Status::invalid_argument(error_messages::INVALID_PAYLOAD));
+    };
+
+    match segments {
+        [last] => {
+            object.insert((*last).to_owned(), value);
+            Ok(())
+        }
+        [next, rest @ ..] => assign(object.entry(*next).or_insert(Value::Null), rest, value),
+        [] => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn mask(paths: &[&str]) -> Option<FieldMask> {
+        Some(FieldMask {
+            paths: paths.iter().map(|p| (*p).to_owned()).collect(),
+        })
+    }
+
+    fn data() -> Value {
+        json!({
+            "title": "Walk the dog",
+            "credits": 2,
+            "location": { "name": "Park", "lat": 1.5 },
+        })
+    }
+
+    fn current() -> Value {
+        json!({
+            "title": "Feed the cat",
+            "credits": 2,
+            "location": { "name": "Home", "lat": 0.5, "lng": 3.0 },
+        })
+    }
+
+    #[test]
+    fn only_includes_the_masked_columns() {
+        let patch = build_patch(&data(), mask(&["title"]).as_ref(), &current(), &[]).unwrap();
+
+        assert_eq!(patch, json!({ "title": "Walk the dog" }));
+    }
+
+    #[test]
+    fn merges_nested_paths_into_the_current_value() {
+        let patch =
+            build_patch(&data(), mask(&["location.name"]).as_ref(), &current(), &[]).unwrap();
+
+        assert_eq!(
+            patch,
+            json!({ "location": { "name": "Park", "lat": 0.5, "lng": 3.0 } })
+        );
+    }
+
+    #[test]
+    fn returns_the_current_value_of_merged_columns() {
+        let mask = mask(&["title", "location.name", "location.lat"]);
+
+        assert_eq!(
+            merged_columns(mask.as_ref(), &current()),
+            vec![(
+                String::from("location"),
+                json!({ "name": "Home", "lat": 0.5, "lng": 3.0 })
+            )]
+        );
+        assert_eq!(
+            merged_columns(mask.as_ref(), &json!({})),
+            vec![(String::from("location"), Value::Null)]
+        );
+    }
+
+    #[test]
+    fn has_no_merged_columns_without_nested_paths() {
+        assert!(merged_columns(mask(&["title", "location"]).as_ref(), &current()).is_empty());
+        assert!(merged_columns(None, &current()).is_empty());
+    }
+
+    #[test]
+    fn creates_the_column_of_a_nested_path_when_it_is_null() {
+        let current = json!({ "location": null });
+
+        let patch = build_patch(&data(), mask(&["location.name"]).as_ref(), &current, &[]).unwrap();
+
+        assert_eq!(patch, json!({ "location": { "name": "Park" } }));
+    }
+
+    #[test]
+    fn rejects_protected_columns() {
+        let err = build_patch(
+            &data(),
+            mask(&["credits"]).as_ref(),
+            &current(),
+            &["credits"],
+        )
+        .unwrap_err();
+
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn rejects_nested_paths_into_protected_columns() {
+        let err = build_patch(
+            &data(),
+            mask(&["location.name"]).as_ref(),
+            &current(),
+            &["location"],
+        )
+        .unwrap_err();
+
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn rejects_unknown_paths() {
+        for path in ["unknown", "location.unknown", "title.nested"] {
+            let err = build_patch(&data(), mask(&[path]).as_ref(), &current(), &[]).unwrap_err();
+
+            assert_eq!(err.code(), tonic::Code::InvalidArgument, "{path}");
+        }
+    }
+
+    #[test]
+    fn rejects_empty_masks() {
+        for mask in [None, mask(&[])] {
+            let err = build_patch(&data(), mask.as_ref(), &current(), &[]).unwrap_err();
+
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn rejects_empty_segments() {
+        let err = build_patch(&data(), mask(&["location."]).as_ref(), &current(), &[]).unwrap_err();
+
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn rejects_overlapping_paths_in_any_order() {
+        for paths in [["location", "location.name"], ["location.name", "location"]] {
+            let err = build_patch(&data(), mask(&paths).as_ref(), &current(), &[]).unwrap_err();
+
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_values() {
+        let data = json!({ "title": "a".repeat(MAX_PATCH_SIZE) });
+
+        let err = build_patch(&data, mask(&["title"]).as_ref(), &current(), &[]).unwrap_err();
+
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn does_not_count_the_current_value_against_the_size() {
+        let current = json!({ "location": { "notes": "a".repeat(MAX_PATCH_SIZE) } });
+
+        let patch = build_patch(&data(), mask(&["location.name"]).as_ref(), &current, &[]).unwrap();
+
+        assert_eq!(patch["location"]["name"], "Park");
+    }
+
+    #[test]
+    fn accepts_paths_that_only_share_a_prefix() {
+        let data = json!({ "location": "Park", "location_name": "Park" });
+        let current = json!({});
+
+        let patch = build_patch(
+            &data,
+            mask(&["location", "location_name"]).as_ref(),
+            &current,
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(data, patch);
+    }
+}
@@ -1,6 +1,10 @@
+pub mod admin;
 pub mod auth;
+mod field_mask;
+pub mod organisation;
 pub mod rating;
 pub mod service_request;
+mod transaction_export;
 pub mod user;
 
 pub type Result<T> = std::result::Result<T, tonic::Status>;
@@ -0,0 +1,259 @@
+pub use crate::proto::organisation::organisation_server::OrganisationServer;
+
+use crate::layers::auth::AuthenticatedUser;
+use crate::proto::organisation::{
+    add_member, create, get_by_id, get_credit_balance, get_members,
+    organisation_server::Organisation, remove_member, update_member_role, Member, MemberRole,
+};
+use crate::services::{error_messages, Result};
+use crate::supabase::{organisation::OrganisationClient, ClientError};
+
+use tonic::{Request, Response, Status};
+
+pub struct OrganisationService {
+    client: OrganisationClient,
+}
+
+impl OrganisationService {
+    pub fn new() -> Self {
+        Self {
+            client: OrganisationClient::new(),
+        }
+    }
+
+    async fn find_member(&self, organisation_id: &str, user_id: &str) -> Result<Member> {
+        match self.client.get_member(organisation_id, user_id).await {
+            Ok(Some(member)) => Ok(member),
+            Ok(None) => Err(Status::permission_denied(
+                "only members of the organisation can do this",
+            )),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    async fn check_admin(&self, organisation_id: &str, user_id: &str) -> Result<()> {
+        let member = self.find_member(organisation_id, user_id).await?;
+
+        if member.role != MemberRole::Admin as i32 {
+            return Err(Status::permission_denied(
+                "only admins of the organisation can do this",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // An organisation must always keep at least one admin, otherwise nobody
+    // could spend its balance or manage its members anymore.
+    async fn check_not_last_admin(&self, organisation_id: &str, user_id: &str) -> Result<()> {
+        let members = match self.client.get_members(organisation_id).await {
+            Ok(values) => values,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        let mut admins = members
+            .iter()
+            .filter(|m| m.role == MemberRole::Admin as i32);
+
+        if admins.all(|m| m.user_id == user_id) {
+            return Err(Status::failed_precondition(
+                "an organisation needs at least one admin",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_role(role: i32) -> Result<MemberRole> {
+    MemberRole::from_i32(role).ok_or_else(|| Status::invalid_argument("invalid member role"))
+}
+
+#[tonic::async_trait]
+impl Organisation for OrganisationService {
+    async fn create(
+        &self,
+        request: Request<create::Request>,
+    ) -> Result<Response<create::Response>> {
+        let creator = AuthenticatedUser::of(&request)?.id.clone();
+
+        let create::Request { data } = request.into_inner();
+
+        let Some(data) = data else {
+            return Err(Status::invalid_argument(error_messages::INVALID_PAYLOAD));
+        };
+
+        if data.name.trim().is_empty() {
+            return Err(Status::invalid_argument(
+                "organisation name cannot be empty",
+            ));
+        }
+
+        let res = self.client.create(creator, data).await;
+
+        match res {
+            Ok(value) => Ok(Response::new(create::Response {
+                organisation: Some(value),
+            })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    async fn get_by_id(
+        &self,
+        request: Request<get_by_id::Request>,
+    ) -> Result<Response<get_by_id::Response>> {
+        let get_by_id::Request { organisation_id } = request.into_inner();
+
+        let res = self.client.get_by_id(organisation_id).await;
+
+        match res {
+            Ok(Some(value)) => Ok(Response::new(get_by_id::Response {
+                organisation: Some(value),
+            })),
+            Ok(None) => Err(Status::not_found("organisation not found")),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // caller is a member of the organisation
+    async fn get_members(
+        &self,
+        request: Request<get_members::Request>,
+    ) -> Result<Response<get_members::Response>> {
+        let caller = AuthenticatedUser::of(&request)?.id.clone();
+
+        let get_members::Request { organisation_id } = request.into_inner();
+
+        self.find_member(&organisation_id, &caller).await?;
+
+        let res = self.client.get_members(organisation_id).await;
+
+        match res {
+            Ok(values) => Ok(Response::new(get_members::Response { members: values })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // caller is an admin of the organisation
+    async fn add_member(
+        &self,
+        request: Request<add_member::Request>,
+    ) -> Result<Response<add_member::Response>> {
+        let caller = AuthenticatedUser::of(&request)?.id.clone();
+
+        let add_member::Request {
+            organisation_id,
+            user_id,
+            role,
+        } = request.into_inner();
+
+        let role = parse_role(role)?;
+        self.check_admin(&organisation_id, &caller).await?;
+
+        let res = self.client.add_member(organisation_id, user_id, role).await;
+
+        match res {
+            Ok(value) => Ok(Response::new(add_member::Response {
+                member: Some(value),
+            })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // caller is an admin of the organisation
+    // the last admin can't be demoted
+    async fn update_member_role(
+        &self,
+        request: Request<update_member_role::Request>,
+    ) -> Result<Response<update_member_role::Response>> {
+        let caller = AuthenticatedUser::of(&request)?.id.clone();
+
+        let update_member_role::Request {
+            organisation_id,
+            user_id,
+            role,
+        } = request.into_inner();
+
+        let role = parse_role(role)?;
+        self.check_admin(&organisation_id, &caller).await?;
+
+        if role != MemberRole::Admin {
+            self.check_not_last_admin(&organisation_id, &user_id)
+                .await?;
+        }
+
+        let res = self
+            .client
+            .update_member_role(organisation_id, user_id, role)
+            .await;
+
+        match res {
+            Ok(value) => Ok(Response::new(update_member_role::Response {
+                member: Some(value),
+            })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // caller is an admin of the organisation, or the member leaving
+    // the last admin can't be removed
+    async fn remove_member(
+        &self,
+        request: Request<remove_member::Request>,
+    ) -> Result<Response<remove_member::Response>> {
+        let caller = AuthenticatedUser::of(&request)?.id.clone();
+
+        let remove_member::Request {
+            organisation_id,
+            user_id,
+        } = request.into_inner();
+
+        if caller != user_id {
+            self.check_admin(&organisation_id, &caller).await?;
+        }
+
+        self.check_not_last_admin(&organisation_id, &user_id)
+            .await?;
+
+        let res = self.client.remove_member(organisation_id, user_id).await;
+
+        match res {
+            Ok(()) => Ok(Response::new(remove_member::Response {})),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // caller is a member of the organisation
+    async fn get_credit_balance(
+        &self,
+        request: Request<get_credit_balance::Request>,
+    ) -> Result<Response<get_credit_balance::Response>> {
+        let caller = AuthenticatedUser::of(&request)?.id.clone();
+
+        let get_credit_balance::Request { organisation_id } = request.into_inner();
+
+        self.find_member(&organisation_id, &caller).await?;
+
+        let res = self.client.get_credit_balance(organisation_id).await;
+
+        match res {
+            Ok(value) => Ok(Response::new(value)),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+}
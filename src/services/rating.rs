@@ -1,36 +1,181 @@
 pub use crate::proto::rating::rating_server::RatingServer;
 
+use crate::layers::auth::{AuthenticatedUser, Role};
 use crate::proto::rating::{
-    create, delete, get, get_by_id, get_for_request, rating_server::Rating, update,
+    create::{self, NewRatingData},
+    delete, get, get_by_id, get_for_request,
+    rating_server::Rating,
+    update, RatingData,
 };
-use crate::services::{error_messages, Result};
-use crate::supabase::{rating::RatingClient, ClientError};
+use crate::services::{error_messages, field_mask, Result};
+use crate::supabase::{
+    rating::RatingClient,
+    service_request::{RequestState, ServiceRequestClient},
+    ClientError,
+};
+
+use std::ops::RangeInclusive;
 
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
 use tonic::{Request, Response, Status};
 
+const RATING_VALUE_RANGE: RangeInclusive<i32> = 1..=5;
+const DEFAULT_EDIT_WINDOW_HOURS: i64 = 48;
+const PROTECTED_FIELDS: &[&str] = &["request_id", "author", "rating_for", "created_at"];
+
+/// Which party of a service request a new rating is about.
+#[derive(Debug, Clone, Copy)]
+enum Ratee {
+    Requestor,
+    Provider,
+}
+
 pub struct RatingService {
     client: RatingClient,
+    requests: ServiceRequestClient,
+    edit_window: Duration,
 }
 
 impl RatingService {
     pub fn new() -> Self {
+        let edit_window = dotenv::var("RATING_EDIT_WINDOW_HOURS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_EDIT_WINDOW_HOURS);
+
         Self {
             client: RatingClient::new(),
+            requests: ServiceRequestClient::new(),
+            edit_window: Duration::hours(edit_window),
         }
     }
+
+    // A rating can only be given once per author, on a completed request,
+    // by the party on the other side of the one being rated. The author must
+    // be the `caller` of the access token.
+    async fn check_can_rate(
+        &self,
+        rating: &NewRatingData,
+        ratee: Ratee,
+        caller: &str,
+    ) -> Result<()> {
+        if rating.author != caller {
+            return Err(Status::permission_denied(
+                "author does not match the access token",
+            ));
+        }
+
+        check_value(rating.value)?;
+
+        let request = match self
+            .requests
+            .get_including_hidden("id", &rating.request_id)
+            .await
+        {
+            Ok(values) => values
+                .into_iter()
+                .next()
+                .ok_or_else(|| Status::not_found("service request not found"))?,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        if RequestState::of(&request) != Some(RequestState::Completed) {
+            return Err(Status::failed_precondition(
+                "only completed service requests can be rated",
+            ));
+        }
+
+        let expected_author = match ratee {
+            Ratee::Requestor => request.provider(),
+            Ratee::Provider => request.requestor.as_str(),
+        };
+
+        if rating.author != expected_author {
+            return Err(Status::permission_denied(
+                "only the other party of the service request can give this rating",
+            ));
+        }
+
+        // hidden ratings still count, otherwise the author could rate again
+        let existing = match self.client.get_all_for_request(&rating.request_id).await {
+            Ok(values) => values,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        if existing.iter().any(|r| r.author == rating.author) {
+            return Err(Status::already_exists(error_messages::ALREADY_EXISTS));
+        }
+
+        Ok(())
+    }
+
+    // Only the author may change a rating, and only within the edit window.
+    async fn check_can_edit(
+        &self,
+        request_id: &str,
+        rating_for: &str,
+        caller: &str,
+    ) -> Result<RatingData> {
+        if caller.is_empty() {
+            return Err(Status::invalid_argument(error_messages::MISSING_ARGUMENT));
+        }
+
+        let rating = match self.client.get_by_id(request_id, rating_for).await {
+            Ok(values) => values
+                .into_iter()
+                .next()
+                .ok_or_else(|| Status::not_found("rating not found"))?,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        if rating.author != caller {
+            return Err(Status::permission_denied(
+                "only the author can change this rating",
+            ));
+        }
+
+        let created_at = OffsetDateTime::parse(&rating.created_at, &Rfc3339)
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        if OffsetDateTime::now_utc() - created_at > self.edit_window {
+            return Err(Status::failed_precondition(
+                "the edit window for this rating has passed",
+            ));
+        }
+
+        Ok(rating)
+    }
+}
+
+fn check_value(value: i32) -> Result<()> {
+    if RATING_VALUE_RANGE.contains(&value) {
+        Ok(())
+    } else {
+        Err(Status::invalid_argument(format!(
+            "rating value must be between {} and {}",
+            RATING_VALUE_RANGE.start(),
+            RATING_VALUE_RANGE.end()
+        )))
+    }
 }
 
 #[tonic::async_trait]
 impl Rating for RatingService {
-    // should retrieve user's auth token
     async fn create_for_requestor(
         &self,
         request: Request<create::Request>,
     ) -> Result<Response<create::Response>> {
+        let caller = AuthenticatedUser::of(&request)?.id.clone();
         let create::Request { rating } = request.into_inner();
 
         match rating {
             Some(data) => {
+                self.check_can_rate(&data, Ratee::Requestor, &caller)
+                    .await?;
+
                 let res = self.client.create_for_requestor(data).await;
 
                 match res {
@@ -38,7 +183,7 @@ impl Rating for RatingService {
                         rating: Some(value),
                     })),
                     Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-                    Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+                    Err(ClientError::InternalError(e)) => Err(e.into()),
                 }
             }
 
@@ -50,10 +195,13 @@ impl Rating for RatingService {
         &self,
         request: Request<create::Request>,
     ) -> Result<Response<create::Response>> {
+        let caller = AuthenticatedUser::of(&request)?.id.clone();
         let create::Request { rating } = request.into_inner();
 
         match rating {
             Some(data) => {
+                self.check_can_rate(&data, Ratee::Provider, &caller).await?;
+
                 let res = self.client.create_for_provider(data).await;
 
                 match res {
@@ -61,7 +209,7 @@ impl Rating for RatingService {
                         rating: Some(value),
                     })),
                     Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-                    Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+                    Err(ClientError::InternalError(e)) => Err(e.into()),
                 }
             }
 
@@ -80,7 +228,7 @@ impl Rating for RatingService {
         match res {
             Ok(values) => Ok(Response::new(get_for_request::Response { ratings: values })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -88,17 +236,34 @@ impl Rating for RatingService {
         &self,
         request: Request<delete::Request>,
     ) -> Result<Response<delete::Response>> {
+        let user = AuthenticatedUser::of(&request)?.clone();
+
         let delete::Request {
             request_id,
             rating_for,
+            caller,
         } = request.into_inner();
 
-        let res = self.client.delete(request_id, rating_for).await;
+        // moderators can remove any rating, the removal is still audited
+        // under their id
+        let editor = if user.role >= Role::Moderator {
+            user.id
+        } else if caller != user.id {
+            return Err(Status::permission_denied(
+                "caller does not match the access token",
+            ));
+        } else {
+            self.check_can_edit(&request_id, &rating_for, &caller)
+                .await?;
+            caller
+        };
+
+        let res = self.client.delete(request_id, rating_for, editor).await;
 
         match res {
             Ok(_) => Ok(Response::new(delete::Response {})),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -106,20 +271,44 @@ impl Rating for RatingService {
         &self,
         request: Request<update::Request>,
     ) -> Result<Response<update::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().caller)?;
+
         let update::Request {
             request_id,
             rating_for,
-            body,
+            caller,
+            data,
+            update_mask,
         } = request.into_inner();
 
-        let res = self.client.update(request_id, rating_for, body).await;
+        let Some(data) = data else {
+            return Err(Status::invalid_argument(error_messages::INVALID_PAYLOAD));
+        };
+
+        if field_mask::contains(update_mask.as_ref(), "value") {
+            check_value(data.value)?;
+        }
+
+        let current = self
+            .check_can_edit(&request_id, &rating_for, &caller)
+            .await?;
+        let current =
+            serde_json::to_value(&current).map_err(|e| Status::internal(e.to_string()))?;
+
+        let body =
+            field_mask::build_patch(&data, update_mask.as_ref(), &current, PROTECTED_FIELDS)?;
+
+        let res = self
+            .client
+            .update(request_id, rating_for, caller, body)
+            .await;
 
         match res {
             Ok(values) => Ok(Response::new(update::Response {
                 rating: values.into_iter().next(),
             })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -139,7 +328,7 @@ impl Rating for RatingService {
                 rating: values.into_iter().next(),
             })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -151,7 +340,7 @@ impl Rating for RatingService {
         match res {
             Ok(ratings) => Ok(Response::new(get::Response { ratings })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 }
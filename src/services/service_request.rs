@@ -1,29 +1,191 @@
+use serde_json::json;
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
 use tonic::{Request, Response, Status};
 use tracing::{info, warn};
 
 use crate::{
+    layers::auth::{AuthenticatedUser, Role},
     proto::servicerequest::{
-        apply_provider, complete_service, create, delete, get, get_available, get_by_id,
-        get_summary_for_user, select_provider, service_request_server::ServiceRequest,
-        start_service, update,
+        add_time_entry, apply_provider, cancel, cancel_series, complete_service, confirm_time_log,
+        create::{self, NewServiceRequestData},
+        delete, get, get_available, get_by_id, get_summary_for_user, get_time_log, open_dispute,
+        resolve_dispute, select_provider,
+        service_request_server::ServiceRequest,
+        start_service, start_timer, stop_timer, update, update_series, withdraw_application,
+        ServiceRequestData,
     },
-    services::{error_messages, Result},
+    scheduler::{self, rrule::RecurrenceRule},
+    services::{error_messages, field_mask, Result},
     starknet::{admin_account::AdminAccount, budi_core_contract::BudiCore},
-    supabase::{service_request::ServiceRequestClient, ClientError},
+    supabase::{
+        organisation::OrganisationClient,
+        series::{Series, SeriesClient},
+        service_request::{DisputeResolution, RequestState, ServiceRequestClient, TimeLogError},
+        user::{HoldRelease, UserClient, INSUFFICIENT_CREDITS},
+        ClientError,
+    },
 };
 
 pub use crate::proto::servicerequest::service_request_server::ServiceRequestServer;
 
+const PROTECTED_FIELDS: &[&str] = &[
+    "id",
+    "requestor",
+    // moving a request onto an organisation's balance needs `check_can_spend`
+    "organisation_id",
+    "provider",
+    "applicants",
+    "state",
+    "actual_payment",
+    "created_at",
+    "completed_at",
+];
+
+const DEFAULT_DISPUTE_WINDOW_HOURS: i64 = 72;
+
 pub struct ServiceRequestService {
     client: ServiceRequestClient,
+    series: SeriesClient,
+    users: UserClient,
+    organisations: OrganisationClient,
+    dispute_window: Duration,
 }
 
 impl ServiceRequestService {
     pub fn new() -> Self {
+        let dispute_window = dotenv::var("DISPUTE_WINDOW_HOURS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_DISPUTE_WINDOW_HOURS);
+
         Self {
             client: ServiceRequestClient::new(),
+            series: SeriesClient::new(),
+            users: UserClient::new(),
+            organisations: OrganisationClient::new(),
+            dispute_window: Duration::hours(dispute_window),
+        }
+    }
+
+    async fn find(&self, request_id: &str) -> Result<ServiceRequestData> {
+        match self.client.get_including_hidden("id", request_id).await {
+            Ok(values) => values
+                .into_iter()
+                .next()
+                .ok_or_else(|| Status::not_found("service request not found")),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    async fn find_series(&self, series_id: &str, caller: &str) -> Result<Series> {
+        let series = match self.series.get_by_id(series_id).await {
+            Ok(value) => value.ok_or_else(|| Status::not_found("series not found"))?,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        if series.requestor != caller {
+            return Err(Status::permission_denied(
+                "only the requestor can change this series",
+            ));
+        }
+
+        if !series.active {
+            return Err(Status::failed_precondition(
+                "this series has been cancelled",
+            ));
+        }
+
+        Ok(series)
+    }
+
+    // Requests made on behalf of an organisation are paid from its shared
+    // balance, which only its admins may spend.
+    async fn check_can_spend(&self, organisation_id: Option<&str>, user_id: &str) -> Result<()> {
+        let Some(organisation_id) = organisation_id.filter(|id| !id.is_empty()) else {
+            return Ok(());
+        };
+
+        match self.organisations.can_spend(organisation_id, user_id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Status::permission_denied(
+                "only admins of the organisation can spend its credits",
+            )),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // A request with a recurrence rule creates a series, whose occurrences
+    // are created ahead of time by the scheduler. The first one is returned.
+    async fn create_series(
+        &self,
+        requestor: String,
+        mut request_data: NewServiceRequestData,
+        recurrence: String,
+    ) -> Result<Response<create::Response>> {
+        recurrence
+            .parse::<RecurrenceRule>()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        request_data.recurrence = None;
+
+        let template =
+            serde_json::to_value(&request_data).map_err(|e| Status::internal(e.to_string()))?;
+
+        let starts_on = template
+            .get("date")
+            .and_then(|d| d.as_str())
+            .and_then(scheduler::series::parse_date)
+            .ok_or_else(|| Status::invalid_argument("a recurring request needs a start date"))?;
+
+        let res = self
+            .series
+            .create(&requestor, template, &recurrence, &starts_on.to_string())
+            .await;
+
+        let series = match res {
+            Ok(value) => value,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        let until = scheduler::series::today() + scheduler::series::horizon();
+        let res = scheduler::series::materialise(&series, &self.series, until).await;
+
+        match res {
+            Ok(created) => Ok(Response::new(create::Response {
+                request: created.into_iter().next(),
+            })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
+
+    // Time can only be logged, and confirmed, while the service is ongoing.
+    async fn find_ongoing(&self, request_id: &str) -> Result<ServiceRequestData> {
+        let request = self.find(request_id).await?;
+
+        if RequestState::of(&request) != Some(RequestState::Ongoing) {
+            return Err(Status::failed_precondition(
+                "time can only be logged while the service is ongoing",
+            ));
+        }
+
+        Ok(request)
+    }
+}
+
+fn time_log_status(e: TimeLogError) -> Status {
+    match &e {
+        TimeLogError::Client(ClientError::SupabaseError(e)) => Status::unknown(e.to_string()),
+        TimeLogError::Client(ClientError::InternalError(e)) => e.into(),
+        TimeLogError::InvalidRange | TimeLogError::InFuture | TimeLogError::InvalidTimestamp(_) => {
+            Status::invalid_argument(e.to_string())
+        }
+        _ => Status::failed_precondition(e.to_string()),
+    }
 }
 
 #[tonic::async_trait]
@@ -32,6 +194,8 @@ impl ServiceRequest for ServiceRequestService {
         &self,
         request: Request<create::Request>,
     ) -> Result<Response<create::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().requestor)?;
+
         let payload = request.into_inner();
 
         match payload {
@@ -39,6 +203,17 @@ impl ServiceRequest for ServiceRequestService {
                 requestor,
                 request_data: Some(request_data),
             } => {
+                self.check_can_spend(request_data.organisation_id.as_deref(), &requestor)
+                    .await?;
+
+                if let Some(recurrence) = request_data.recurrence.clone() {
+                    if !recurrence.trim().is_empty() {
+                        return self
+                            .create_series(requestor, request_data, recurrence)
+                            .await;
+                    }
+                }
+
                 let res = self.client.create(requestor, request_data).await;
 
                 match res {
@@ -46,7 +221,7 @@ impl ServiceRequest for ServiceRequestService {
                         request: Some(value),
                     })),
                     Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-                    Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+                    Err(ClientError::InternalError(e)) => Err(e.into()),
                 }
             }
 
@@ -54,40 +229,89 @@ impl ServiceRequest for ServiceRequestService {
         }
     }
 
-    // updating a column with json-type value must also include all values
-    // that are not being updated
     async fn update(
         &self,
         request: Request<update::Request>,
     ) -> Result<Response<update::Response>> {
-        let update::Request { request_id, body } = request.into_inner();
+        AuthenticatedUser::acting_as(&request, &request.get_ref().caller)?;
+
+        let update::Request {
+            request_id,
+            caller,
+            data,
+            update_mask,
+        } = request.into_inner();
+
+        let Some(data) = data else {
+            return Err(Status::invalid_argument(error_messages::INVALID_PAYLOAD));
+        };
+
+        let current = self.find(&request_id).await?;
 
-        let res = self.client.update(request_id, body).await;
+        if current.requestor != caller {
+            return Err(Status::permission_denied(
+                "only the requestor can update this service request",
+            ));
+        }
+
+        // once accepted, the provider agreed to the request as it is
+        if RequestState::of(&current) != Some(RequestState::Pending) {
+            return Err(Status::failed_precondition(
+                "only pending service requests can be updated",
+            ));
+        }
+
+        let current =
+            serde_json::to_value(&current).map_err(|e| Status::internal(e.to_string()))?;
+        let body =
+            field_mask::build_patch(&data, update_mask.as_ref(), &current, PROTECTED_FIELDS)?;
+        let unchanged = field_mask::merged_columns(update_mask.as_ref(), &current);
+
+        let res = self
+            .client
+            .update(request_id, body.to_string(), &unchanged)
+            .await;
 
         match res {
-            Ok(values) => Ok(Response::new(update::Response {
-                request: values.into_iter().next(),
-            })),
+            Ok(values) => match values.into_iter().next() {
+                Some(request) => Ok(Response::new(update::Response {
+                    request: Some(request),
+                })),
+                None => Err(Status::aborted(
+                    "the service request was changed by another update, retry",
+                )),
+            },
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
+    // CONDITIONS :
+    // 1. MUST only be called by the requestor, or a moderator
     async fn delete(
         &self,
         request: Request<delete::Request>,
     ) -> Result<Response<delete::Response>> {
+        let user = AuthenticatedUser::of(&request)?.clone();
         let payload = request.into_inner();
 
         if payload.request_id.is_empty() {
             Err(Status::invalid_argument(error_messages::INVALID_PAYLOAD))
         } else {
+            let current = self.find(&payload.request_id).await?;
+
+            if current.requestor != user.id && user.role < Role::Moderator {
+                return Err(Status::permission_denied(
+                    "only the requestor can delete this service request",
+                ));
+            }
+
             let res = self.client.delete(payload.request_id).await;
 
             match res {
                 Ok(()) => Ok(Response::new(delete::Response {})),
                 Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-                Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+                Err(ClientError::InternalError(e)) => Err(e.into()),
             }
         }
     }
@@ -100,7 +324,7 @@ impl ServiceRequest for ServiceRequestService {
         match res {
             Ok(values) => Ok(Response::new(get::Response { requests: values })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -115,7 +339,7 @@ impl ServiceRequest for ServiceRequestService {
         match res {
             Ok(value) => Ok(Response::new(value)),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -123,12 +347,34 @@ impl ServiceRequest for ServiceRequestService {
         &self,
         request: Request<complete_service::Request>,
     ) -> Result<Response<complete_service::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().user_id)?;
+
         let complete_service::Request {
             request_id,
             user_id,
         } = request.into_inner();
 
-        let res = self.client.complete_service(request_id, user_id).await;
+        let log = match self.client.get_time_log(&request_id).await {
+            Ok(log) => log,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        // the payment is derived from the confirmed time log, if time was logged
+        let actual_payment = match log.confirmation {
+            Some(confirmation) => Some(confirmation.confirmed_minutes as f32 / 60.0),
+            None if log.entries.is_empty() => None,
+            None => {
+                return Err(Status::failed_precondition(
+                    "the time log must be confirmed by the requestor before completing",
+                ))
+            }
+        };
+
+        let res = self
+            .client
+            .complete_service(request_id, user_id, actual_payment)
+            .await;
 
         match res {
             Ok(request) => {
@@ -155,10 +401,21 @@ impl ServiceRequest for ServiceRequestService {
                     ),
                 }
 
+                if let Err(e) = self
+                    .users
+                    .release_credit_hold(&request.id, HoldRelease::Settled)
+                    .await
+                {
+                    warn!(
+                        "error when releasing hold for request_id={}, the scheduler will retry error={e}",
+                        request.id
+                    );
+                }
+
                 Ok(Response::new(complete_service::Response {}))
             }
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -166,6 +423,8 @@ impl ServiceRequest for ServiceRequestService {
         &self,
         request: Request<apply_provider::Request>,
     ) -> Result<Response<apply_provider::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().provider)?;
+
         let apply_provider::Request {
             request_id,
             provider,
@@ -176,7 +435,7 @@ impl ServiceRequest for ServiceRequestService {
         match res {
             Ok(()) => Ok(Response::new(apply_provider::Response {})),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -186,21 +445,71 @@ impl ServiceRequest for ServiceRequestService {
         &self,
         request: Request<select_provider::Request>,
     ) -> Result<Response<select_provider::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().caller)?;
+
         let select_provider::Request {
             request_id,
             provider,
             caller,
         } = request.into_inner();
 
+        let current = self.find(&request_id).await?;
+
+        if current.requestor != caller {
+            return Err(Status::permission_denied(
+                "only the requestor can select a provider",
+            ));
+        }
+
+        self.check_can_spend(current.organisation_id.as_deref(), &caller)
+            .await?;
+
+        // the estimated payment is held before the provider is selected, so
+        // the requestor, or its organisation, can't end up short of credits
+        // at completion
+        let res = self
+            .users
+            .place_credit_hold(&request_id, &caller, current.organisation_id.as_deref())
+            .await;
+
+        match res {
+            Ok(hold) => info!(
+                "placed hold of {} for request_id={}",
+                hold.amount, request_id
+            ),
+            Err(ClientError::SupabaseError(e)) if e.code == INSUFFICIENT_CREDITS => {
+                return Err(Status::failed_precondition(
+                    match current.organisation_id.as_deref() {
+                        Some(id) if !id.is_empty() => {
+                            "the organisation doesn't have enough credits available"
+                        }
+                        _ => "the requestor doesn't have enough credits available",
+                    },
+                ))
+            }
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        }
+
         let res = self
             .client
-            .select_provider(request_id, provider, caller)
+            .select_provider(&request_id, provider, caller)
             .await;
 
+        if res.is_err() {
+            if let Err(e) = self
+                .users
+                .release_credit_hold(&request_id, HoldRelease::Refunded)
+                .await
+            {
+                warn!("error when releasing hold for request_id={request_id}, the scheduler will retry error={e}");
+            }
+        }
+
         match res {
             Ok(()) => Ok(Response::new(select_provider::Response {})),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -208,6 +517,8 @@ impl ServiceRequest for ServiceRequestService {
         &self,
         request: Request<start_service::Request>,
     ) -> Result<Response<start_service::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().user_id)?;
+
         let start_service::Request {
             user_id,
             request_id,
@@ -218,7 +529,7 @@ impl ServiceRequest for ServiceRequestService {
         match res {
             Ok(()) => Ok(Response::new(start_service::Response {})),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -249,7 +560,7 @@ impl ServiceRequest for ServiceRequestService {
         match res {
             Ok(requests) => Ok(Response::new(get_available::Response { requests })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -264,7 +575,513 @@ impl ServiceRequest for ServiceRequestService {
         match res {
             Ok(value) => Ok(Response::new(value)),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. MUST only be called by `provider`
+    async fn withdraw_application(
+        &self,
+        request: Request<withdraw_application::Request>,
+    ) -> Result<Response<withdraw_application::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().provider)?;
+
+        let withdraw_application::Request {
+            request_id,
+            provider,
+        } = request.into_inner();
+
+        let current = self.find(&request_id).await?;
+
+        // once a provider is selected, they have to cancel instead
+        if RequestState::of(&current) != Some(RequestState::Pending) {
+            return Err(Status::failed_precondition(
+                "applications can only be withdrawn while the request is pending",
+            ));
+        }
+
+        let res = self.client.withdraw_application(request_id, provider).await;
+
+        match res {
+            Ok(()) => Ok(Response::new(withdraw_application::Response {})),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. pending requests can only be cancelled by the requestor
+    // 2. accepted requests can be cancelled by the requestor or the selected provider
+    // 3. ongoing and completed requests cannot be cancelled
+    async fn cancel(
+        &self,
+        request: Request<cancel::Request>,
+    ) -> Result<Response<cancel::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().caller)?;
+
+        let cancel::Request {
+            request_id,
+            caller,
+            reason,
+        } = request.into_inner();
+
+        if reason.trim().is_empty() {
+            return Err(Status::invalid_argument(
+                "a reason is required to cancel a service request",
+            ));
+        }
+
+        let current = self.find(&request_id).await?;
+        let is_requestor = current.requestor == caller;
+        let is_provider = current.provider() == caller;
+
+        match RequestState::of(&current) {
+            Some(RequestState::Pending) if is_requestor => {}
+            Some(RequestState::Accepted) if is_requestor || is_provider => {}
+
+            Some(RequestState::Pending | RequestState::Accepted) => {
+                return Err(Status::permission_denied(
+                    "only the requestor or the selected provider can cancel this service request",
+                ))
+            }
+
+            Some(RequestState::Ongoing) => {
+                return Err(Status::failed_precondition(
+                    "an ongoing service request cannot be cancelled, complete it and open a dispute instead",
+                ))
+            }
+
+            _ => {
+                return Err(Status::failed_precondition(
+                    "this service request can no longer be cancelled",
+                ))
+            }
+        }
+
+        let res = self.client.cancel(&request_id, caller, reason).await;
+
+        match res {
+            Ok(value) => {
+                if RequestState::of(&current) == Some(RequestState::Accepted) {
+                    if let Err(e) = self
+                        .users
+                        .release_credit_hold(&request_id, HoldRelease::Refunded)
+                        .await
+                    {
+                        warn!("error when refunding hold for request_id={request_id}, the scheduler will retry error={e}");
+                    }
+                }
+
+                Ok(Response::new(cancel::Response {
+                    request: Some(value),
+                }))
+            }
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. the request MUST be completed, and not longer ago than the dispute window
+    // 2. MUST only be called by the requestor or the provider of `request_id`
+    async fn open_dispute(
+        &self,
+        request: Request<open_dispute::Request>,
+    ) -> Result<Response<open_dispute::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().caller)?;
+
+        let open_dispute::Request {
+            request_id,
+            caller,
+            reason,
+            claimed_payment,
+        } = request.into_inner();
+
+        if reason.trim().is_empty() {
+            return Err(Status::invalid_argument(
+                "a reason is required to open a dispute",
+            ));
+        }
+
+        if matches!(claimed_payment, Some(value) if value < 0.0) {
+            return Err(Status::invalid_argument(
+                "claimed payment cannot be negative",
+            ));
+        }
+
+        let current = self.find(&request_id).await?;
+
+        if RequestState::of(&current) != Some(RequestState::Completed) {
+            return Err(Status::failed_precondition(
+                "only completed service requests can be disputed",
+            ));
+        }
+
+        if current.requestor != caller && current.provider() != caller {
+            return Err(Status::permission_denied(
+                "only the requestor or the provider can dispute this service request",
+            ));
+        }
+
+        let completed_at = OffsetDateTime::parse(current.completed_at(), &Rfc3339)
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        if OffsetDateTime::now_utc() - completed_at > self.dispute_window {
+            return Err(Status::failed_precondition(
+                "the dispute window for this service request has passed",
+            ));
+        }
+
+        let res = self
+            .client
+            .open_dispute(request_id, caller, reason, claimed_payment)
+            .await;
+
+        match res {
+            Ok(value) => Ok(Response::new(open_dispute::Response {
+                dispute: Some(value),
+            })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. MUST only be called by an admin
+    async fn resolve_dispute(
+        &self,
+        request: Request<resolve_dispute::Request>,
+    ) -> Result<Response<resolve_dispute::Response>> {
+        let caller = AuthenticatedUser::with_role(&request, Role::Admin)?
+            .id
+            .clone();
+
+        let resolve_dispute::Request {
+            dispute_id,
+            actual_payment,
+            note,
+        } = request.into_inner();
+
+        if actual_payment < 0.0 {
+            return Err(Status::invalid_argument(
+                "actual payment cannot be negative",
+            ));
+        }
+
+        let res = self
+            .client
+            .resolve_dispute(dispute_id, caller, actual_payment, note)
+            .await;
+
+        match res {
+            Ok(DisputeResolution {
+                dispute,
+                request,
+                previous_payment,
+            }) => {
+                // the original commitment is only corrected if the payment changed
+                if (request.actual_payment - previous_payment).abs() > f32::EPSILON {
+                    let admin = AdminAccount::new();
+                    let res = BudiCore::new(admin)
+                        .commit_payment_correction(
+                            request.id.as_str(),
+                            request.requestor.as_str(),
+                            request.provider(),
+                            request.actual_payment,
+                            dispute.resolved_at(),
+                        )
+                        .await;
+
+                    match res {
+                        Ok(tx) => info!(
+                            "correction submitted for request_id={} tx_hash={:#x}",
+                            request.id, tx.transaction_hash
+                        ),
+                        Err(e) => warn!(
+                            "error when submitting correction for request_id={} error={e}",
+                            request.id
+                        ),
+                    }
+                }
+
+                Ok(Response::new(resolve_dispute::Response {
+                    dispute: Some(dispute),
+                    request: Some(request),
+                }))
+            }
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. MUST only be called by the provider of `request_id`
+    async fn start_timer(
+        &self,
+        request: Request<start_timer::Request>,
+    ) -> Result<Response<start_timer::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().provider)?;
+
+        let start_timer::Request {
+            request_id,
+            provider,
+        } = request.into_inner();
+
+        let current = self.find_ongoing(&request_id).await?;
+
+        if current.provider() != provider {
+            return Err(Status::permission_denied(
+                "only the provider can log time for this service request",
+            ));
+        }
+
+        match self.client.start_timer(&request_id, &provider).await {
+            Ok(entry) => Ok(Response::new(start_timer::Response { entry: Some(entry) })),
+            Err(e) => Err(time_log_status(e)),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. MUST only be called by the provider of `request_id`
+    async fn stop_timer(
+        &self,
+        request: Request<stop_timer::Request>,
+    ) -> Result<Response<stop_timer::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().provider)?;
+
+        let stop_timer::Request {
+            request_id,
+            provider,
+        } = request.into_inner();
+
+        let current = self.find_ongoing(&request_id).await?;
+
+        if current.provider() != provider {
+            return Err(Status::permission_denied(
+                "only the provider can log time for this service request",
+            ));
+        }
+
+        match self.client.stop_timer(&request_id, &provider).await {
+            Ok(entry) => Ok(Response::new(stop_timer::Response { entry: Some(entry) })),
+            Err(e) => Err(time_log_status(e)),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. MUST only be called by the provider of `request_id`
+    async fn add_time_entry(
+        &self,
+        request: Request<add_time_entry::Request>,
+    ) -> Result<Response<add_time_entry::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().provider)?;
+
+        let add_time_entry::Request {
+            request_id,
+            provider,
+            started_at,
+            ended_at,
+        } = request.into_inner();
+
+        let current = self.find_ongoing(&request_id).await?;
+
+        if current.provider() != provider {
+            return Err(Status::permission_denied(
+                "only the provider can log time for this service request",
+            ));
+        }
+
+        let res = self
+            .client
+            .add_time_entry(&request_id, &provider, &started_at, &ended_at)
+            .await;
+
+        match res {
+            Ok(entry) => Ok(Response::new(add_time_entry::Response {
+                entry: Some(entry),
+            })),
+            Err(e) => Err(time_log_status(e)),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. MUST only be called by the requestor or provider of `request_id`, or
+    //    by a moderator
+    async fn get_time_log(
+        &self,
+        request: Request<get_time_log::Request>,
+    ) -> Result<Response<get_time_log::Response>> {
+        let user = AuthenticatedUser::of(&request)?.clone();
+        let get_time_log::Request { request_id } = request.into_inner();
+
+        let current = self.find(&request_id).await?;
+
+        if current.requestor != user.id
+            && current.provider() != user.id
+            && user.role < Role::Moderator
+        {
+            return Err(Status::permission_denied(
+                "only the requestor or provider can view the time log",
+            ));
+        }
+
+        let res = self.client.get_time_log(&request_id).await;
+
+        match res {
+            Ok(log) => Ok(Response::new(get_time_log::Response { log: Some(log) })),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // 1. MUST only be called by the requestor of `request_id`
+    async fn confirm_time_log(
+        &self,
+        request: Request<confirm_time_log::Request>,
+    ) -> Result<Response<confirm_time_log::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().requestor)?;
+
+        let confirm_time_log::Request {
+            request_id,
+            requestor,
+            counter_minutes,
+        } = request.into_inner();
+
+        let current = self.find_ongoing(&request_id).await?;
+
+        if current.requestor != requestor {
+            return Err(Status::permission_denied(
+                "only the requestor can confirm the time log",
+            ));
+        }
+
+        let res = self
+            .client
+            .confirm_time_log(&request_id, &requestor, counter_minutes)
+            .await;
+
+        match res {
+            Ok(log) => Ok(Response::new(confirm_time_log::Response { log: Some(log) })),
+            Err(e) => Err(time_log_status(e)),
+        }
+    }
+
+    // Changing a series replaces its pending future occurrences. Occurrences
+    // that already have a provider selected are kept as they are.
+    async fn update_series(
+        &self,
+        request: Request<update_series::Request>,
+    ) -> Result<Response<update_series::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().caller)?;
+
+        let update_series::Request {
+            series_id,
+            caller,
+            request_data,
+        } = request.into_inner();
+
+        let Some(mut request_data) = request_data else {
+            return Err(Status::invalid_argument(error_messages::INVALID_PAYLOAD));
+        };
+
+        let series = self.find_series(&series_id, &caller).await?;
+
+        self.check_can_spend(request_data.organisation_id.as_deref(), &caller)
+            .await?;
+
+        let recurrence = match request_data.recurrence.take() {
+            Some(recurrence) if !recurrence.trim().is_empty() => recurrence,
+            _ => series.recurrence.clone(),
+        };
+
+        recurrence
+            .parse::<RecurrenceRule>()
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let template =
+            serde_json::to_value(&request_data).map_err(|e| Status::internal(e.to_string()))?;
+
+        let starts_on = template
+            .get("date")
+            .and_then(|d| d.as_str())
+            .and_then(scheduler::series::parse_date)
+            .map(|d| d.to_string())
+            .unwrap_or_else(|| series.starts_on.clone());
+
+        let today = scheduler::series::today();
+
+        let res = self
+            .series
+            .delete_pending_occurrences(&series.id, today.to_string())
+            .await;
+
+        match res {
+            Ok(()) => {}
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        }
+
+        let res = self
+            .series
+            .update(
+                &series.id,
+                json!({
+                    "template": template,
+                    "recurrence": recurrence,
+                    "starts_on": starts_on,
+                    "materialized_until": null,
+                }),
+            )
+            .await;
+
+        let series = match res {
+            Ok(value) => value,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        let until = today + scheduler::series::horizon();
+        let res = scheduler::series::materialise(&series, &self.series, until).await;
+
+        match res {
+            Ok(_) => Ok(Response::new(update_series::Response {})),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    async fn cancel_series(
+        &self,
+        request: Request<cancel_series::Request>,
+    ) -> Result<Response<cancel_series::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().caller)?;
+
+        let cancel_series::Request { series_id, caller } = request.into_inner();
+
+        let series = self.find_series(&series_id, &caller).await?;
+
+        let res = self
+            .series
+            .update(&series.id, json!({ "active": false }))
+            .await;
+
+        match res {
+            Ok(_) => {}
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        }
+
+        let res = self
+            .series
+            .delete_pending_occurrences(&series.id, scheduler::series::today().to_string())
+            .await;
+
+        match res {
+            Ok(()) => Ok(Response::new(cancel_series::Response {})),
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 }
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+use crate::proto::user::{CreditTransaction, ExportFormat};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    earned: f32,
+    spent: f32,
+}
+
+/// Formats the transactions of a user as CSV or JSON lines, one page at a
+/// time, and keeps monthly totals for the statement at the end of an export.
+pub struct TransactionExporter {
+    user_id: String,
+    format: ExportFormat,
+    months: BTreeMap<String, Totals>,
+}
+
+impl TransactionExporter {
+    pub fn new(user_id: impl Into<String>, format: ExportFormat) -> Self {
+        Self {
+            user_id: user_id.into(),
+            format,
+            months: BTreeMap::new(),
+        }
+    }
+
+    pub fn header(&self) -> Option<String> {
+        match self.format {
+            ExportFormat::Csv => Some("id,created_at,kind,direction,counterparty,amount\n".into()),
+            ExportFormat::JsonLines => None,
+        }
+    }
+
+    pub fn records(&mut self, transactions: &[CreditTransaction]) -> String {
+        transactions.iter().map(|tx| self.record(tx)).collect()
+    }
+
+    fn record(&mut self, tx: &CreditTransaction) -> String {
+        let earned = tx.recipient == self.user_id;
+        let (direction, counterparty) = if earned {
+            ("earned", &tx.sender)
+        } else {
+            ("spent", &tx.recipient)
+        };
+
+        // `created_at` starts with YYYY-MM
+        let month = tx.created_at.get(..7).unwrap_or_default().to_owned();
+        let totals = self.months.entry(month).or_default();
+
+        if earned {
+            totals.earned += tx.amount;
+        } else {
+            totals.spent += tx.amount;
+        }
+
+        match self.format {
+            ExportFormat::Csv => format!(
+                "{},{},{},{},{},{}\n",
+                csv_field(&tx.id),
+                csv_field(&tx.created_at),
+                csv_field(&tx.kind),
+                direction,
+                csv_field(counterparty),
+                tx.amount
+            ),
+
+            ExportFormat::JsonLines => format!(
+                "{}\n",
+                json!({
+                    "id": tx.id,
+                    "created_at": tx.created_at,
+                    "kind": tx.kind,
+                    "direction": direction,
+                    "counterparty": counterparty,
+                    "amount": tx.amount,
+                })
+            ),
+        }
+    }
+
+    /// Earned, spent and net credits per month of the exported records,
+    /// followed by the totals.
+    pub fn statement(&self) -> String {
+        let total = self
+            .months
+            .values()
+            .fold(Totals::default(), |acc, t| Totals {
+                earned: acc.earned + t.earned,
+                spent: acc.spent + t.spent,
+            });
+
+        let rows = self
+            .months
+            .iter()
+            .map(|(month, totals)| (month.as_str(), *totals))
+            .chain(std::iter::once(("total", total)));
+
+        match self.format {
+            ExportFormat::Csv => std::iter::once("\nperiod,earned,spent,net\n".to_owned())
+                .chain(rows.map(|(period, t)| {
+                    format!("{period},{},{},{}\n", t.earned, t.spent, t.earned - t.spent)
+                }))
+                .collect(),
+
+            ExportFormat::JsonLines => rows
+                .map(|(period, t)| {
+                    format!(
+                        "{}\n",
+                        json!({
+                            "statement": {
+                                "period": period,
+                                "earned": t.earned,
+                                "spent": t.spent,
+                                "net": t.earned - t.spent,
+                            }
+                        })
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
@@ -1,24 +1,66 @@
+use std::pin::Pin;
+
+use futures::Stream;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
+use tracing::{info, warn, Instrument};
 
+use crate::layers::{auth::AuthenticatedUser, logger};
+use crate::proto::moderation::ReportTarget;
 pub use crate::proto::user::user_server::UserServer;
 use crate::proto::user::{
-    get, get_by_id, get_credit_balance, get_profile, get_rating, get_transaction_history, update,
-    user_server::User,
+    export_transaction_history, get, get_by_id, get_credit_balance, get_profile, get_rating,
+    get_transaction_history, report, transfer_credits, transfer_credits::request::Recipient,
+    update, user_server::User, ExportFormat, TransactionFilter,
+};
+use crate::services::{
+    error_messages, field_mask, transaction_export::TransactionExporter, Result,
 };
-use crate::services::Result;
-use crate::supabase::user::UserClient;
+use crate::starknet::{admin_account::AdminAccount, budi_core_contract::BudiCore};
+use crate::supabase::moderation::ModerationClient;
+use crate::supabase::user::{HistoryCursor, UserClient, INSUFFICIENT_CREDITS};
 use crate::supabase::ClientError;
 
+const PROTECTED_FIELDS: &[&str] = &["user_id", "email", "credit_balance", "created_at"];
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+const EXPORT_PAGE_SIZE: usize = 500;
+const MAX_MEMO_LENGTH: usize = 280;
+const MAX_REPORT_REASON_LENGTH: usize = 1000;
+
 pub struct UserService {
     client: UserClient,
+    moderation: ModerationClient,
+    commit_transfers: bool,
 }
 
 impl UserService {
     pub fn new() -> Self {
+        let commit_transfers = dotenv::var("COMMIT_TRANSFERS_ONCHAIN")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+
         Self {
             client: UserClient::new(),
+            moderation: ModerationClient::new(),
+            commit_transfers,
+        }
+    }
+}
+
+fn check_filter(filter: &TransactionFilter) -> Result<()> {
+    for date in [&filter.from, &filter.to].into_iter().flatten() {
+        if OffsetDateTime::parse(date, &Rfc3339).is_err() {
+            return Err(Status::invalid_argument(format!(
+                "invalid date `{date}`, expected an RFC 3339 timestamp"
+            )));
         }
     }
+
+    Ok(())
 }
 
 #[tonic::async_trait]
@@ -31,7 +73,7 @@ impl User for UserService {
         match res {
             Ok(values) => Ok(Response::new(get::Response { users: values })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -48,7 +90,7 @@ impl User for UserService {
                 user: values.into_iter().next(),
             })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -56,14 +98,45 @@ impl User for UserService {
         &self,
         request: Request<update::Request>,
     ) -> Result<Response<update::Response>> {
-        let update::Request { user_id, body } = request.into_inner();
+        AuthenticatedUser::acting_as(&request, &request.get_ref().user_id)?;
+
+        let update::Request {
+            user_id,
+            data,
+            update_mask,
+        } = request.into_inner();
+
+        let Some(data) = data else {
+            return Err(Status::invalid_argument(error_messages::INVALID_PAYLOAD));
+        };
 
-        let res = self.client.update(user_id, body).await;
+        let current = match self.client.get("user_id", &user_id).await {
+            Ok(values) => values
+                .into_iter()
+                .next()
+                .ok_or_else(|| Status::not_found("user not found"))?,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        let current =
+            serde_json::to_value(&current).map_err(|e| Status::internal(e.to_string()))?;
+        let body =
+            field_mask::build_patch(&data, update_mask.as_ref(), &current, PROTECTED_FIELDS)?;
+        let unchanged = field_mask::merged_columns(update_mask.as_ref(), &current);
+
+        let res = self
+            .client
+            .update(user_id, body.to_string(), &unchanged)
+            .await;
 
         match res {
-            Ok(value) => Ok(Response::new(update::Response { user: Some(value) })),
+            Ok(Some(value)) => Ok(Response::new(update::Response { user: Some(value) })),
+            Ok(None) => Err(Status::aborted(
+                "the profile was changed by another update, retry",
+            )),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -82,7 +155,7 @@ impl User for UserService {
         match res {
             Ok(value) => Ok(Response::new(get_profile::Response { user: Some(value) })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -98,6 +171,8 @@ impl User for UserService {
         &self,
         request: Request<get_credit_balance::Request>,
     ) -> Result<Response<get_credit_balance::Response>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().user_id)?;
+
         let get_credit_balance::Request { user_id } = request.into_inner();
 
         let res = self.client.get_credit_balance(&user_id).await;
@@ -105,7 +180,7 @@ impl User for UserService {
         match res {
             Ok(value) => Ok(Response::new(value)),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 
@@ -113,14 +188,316 @@ impl User for UserService {
         &self,
         request: Request<get_transaction_history::Request>,
     ) -> Result<Response<get_transaction_history::Response>> {
-        let get_transaction_history::Request { user_id } = request.into_inner();
+        AuthenticatedUser::acting_as(&request, &request.get_ref().user_id)?;
+
+        let get_transaction_history::Request {
+            user_id,
+            filter,
+            page_size,
+            cursor,
+        } = request.into_inner();
+
+        let filter = filter.unwrap_or_default();
+        check_filter(&filter)?;
+
+        let page_size = match page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            n => n.min(MAX_PAGE_SIZE),
+        } as usize;
+
+        let after = match cursor.filter(|c| !c.is_empty()) {
+            Some(cursor) => Some(
+                HistoryCursor::decode(&cursor)
+                    .ok_or_else(|| Status::invalid_argument("invalid cursor"))?,
+            ),
+            None => None,
+        };
+
+        // one more than requested, to know whether there is a next page
+        let res = self
+            .client
+            .get_transaction_history(&user_id, &filter, after.as_ref(), page_size + 1)
+            .await;
+
+        match res {
+            Ok(mut data) => {
+                let next_cursor = if data.len() > page_size {
+                    data.truncate(page_size);
+                    data.last().map(|tx| HistoryCursor::after(tx).encode())
+                } else {
+                    None
+                };
+
+                Ok(Response::new(get_transaction_history::Response {
+                    data,
+                    next_cursor,
+                }))
+            }
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    type ExportTransactionHistoryStream =
+        Pin<Box<dyn Stream<Item = Result<export_transaction_history::Response>> + Send>>;
+
+    async fn export_transaction_history(
+        &self,
+        request: Request<export_transaction_history::Request>,
+    ) -> Result<Response<Self::ExportTransactionHistoryStream>> {
+        AuthenticatedUser::acting_as(&request, &request.get_ref().user_id)?;
+
+        let export_transaction_history::Request {
+            user_id,
+            filter,
+            format,
+            statement,
+        } = request.into_inner();
+
+        if user_id.is_empty() {
+            return Err(Status::invalid_argument("user id cannot be empty"));
+        }
+
+        let filter = filter.unwrap_or_default();
+        check_filter(&filter)?;
+
+        let format = ExportFormat::from_i32(format)
+            .ok_or_else(|| Status::invalid_argument("unknown export format"))?;
+
+        let (tx, rx) = mpsc::channel(4);
+
+        let export = async move {
+            let client = UserClient::new();
+            let mut exporter = TransactionExporter::new(&user_id, format);
+            let mut after = None;
+
+            if let Some(header) = exporter.header() {
+                let chunk = export_transaction_history::Response { chunk: header };
+
+                if tx.send(Ok(chunk)).await.is_err() {
+                    return;
+                }
+            }
+
+            loop {
+                let res = client
+                    .get_transaction_history(&user_id, &filter, after.as_ref(), EXPORT_PAGE_SIZE)
+                    .await;
+
+                let page = match res {
+                    Ok(page) => page,
+                    Err(e) => {
+                        let status = match e {
+                            ClientError::SupabaseError(e) => Status::unknown(e.to_string()),
+                            ClientError::InternalError(e) => e.into(),
+                        };
+
+                        let _ = tx.send(Err(status)).await;
+                        return;
+                    }
+                };
+
+                let chunk = export_transaction_history::Response {
+                    chunk: exporter.records(&page),
+                };
+
+                // the client has gone away
+                if !chunk.chunk.is_empty() && tx.send(Ok(chunk)).await.is_err() {
+                    return;
+                }
+
+                if page.len() < EXPORT_PAGE_SIZE {
+                    break;
+                }
 
-        let res = self.client.get_transaction_history(&user_id).await;
+                after = page.last().map(HistoryCursor::after);
+            }
+
+            if statement {
+                let chunk = export_transaction_history::Response {
+                    chunk: exporter.statement(),
+                };
+
+                let _ = tx.send(Ok(chunk)).await;
+            }
+        };
+
+        // the export runs in its own task, which is given the span and request
+        // id of the RPC
+        let export = export.in_current_span();
+
+        match logger::current_request_id() {
+            Some(request_id) => tokio::spawn(logger::scope_request_id(request_id, export)),
+            None => tokio::spawn(export),
+        };
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    // CONDITIONS :
+    // sender is the authenticated caller
+    // amount is positive and at most the available balance of the sender
+    // recipient is not the sender
+    // a retried request with the same idempotency key returns the original transfer
+    async fn transfer_credits(
+        &self,
+        request: Request<transfer_credits::Request>,
+    ) -> Result<Response<transfer_credits::Response>> {
+        let sender = AuthenticatedUser::of(&request)?.id.clone();
+
+        let transfer_credits::Request {
+            recipient,
+            amount,
+            memo,
+            idempotency_key,
+        } = request.into_inner();
+
+        let Some(recipient) = recipient else {
+            return Err(Status::invalid_argument("missing recipient"));
+        };
+
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(Status::invalid_argument("amount must be greater than zero"));
+        }
+
+        if memo.chars().count() > MAX_MEMO_LENGTH {
+            return Err(Status::invalid_argument(format!(
+                "memo cannot be longer than {MAX_MEMO_LENGTH} characters"
+            )));
+        }
+
+        if idempotency_key.is_empty() {
+            return Err(Status::invalid_argument("missing idempotency key"));
+        }
+
+        if matches!(&recipient, Recipient::UserId(id) if *id == sender) {
+            return Err(Status::invalid_argument(
+                "cannot transfer credits to yourself",
+            ));
+        }
+
+        // a retry is answered before the balance check, which the original
+        // transfer may have changed
+        match self
+            .client
+            .get_transfer_by_key(&sender, &idempotency_key)
+            .await
+        {
+            Ok(Some(transaction)) => {
+                return Ok(Response::new(transfer_credits::Response {
+                    transaction: Some(transaction),
+                }))
+            }
+            Ok(None) => {}
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        }
+
+        let balance = match self.client.get_credit_balance(&sender).await {
+            Ok(balance) => balance,
+            Err(ClientError::SupabaseError(e)) => return Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => return Err(e.into()),
+        };
+
+        if balance.available < amount {
+            return Err(Status::failed_precondition("insufficient credits"));
+        }
+
+        let res = self
+            .client
+            .transfer_credits(&sender, &recipient, amount, &memo, &idempotency_key)
+            .await;
 
         match res {
-            Ok(data) => Ok(Response::new(get_transaction_history::Response { data })),
+            Ok(transfer) => {
+                let transaction = transfer.transaction;
+
+                if self.commit_transfers && !transfer.replayed {
+                    let admin = AdminAccount::new();
+                    let res = BudiCore::new(admin)
+                        .commit_credit_transfer(
+                            &transaction.id,
+                            &transaction.sender,
+                            &transaction.recipient,
+                            transaction.amount,
+                            &transaction.created_at,
+                        )
+                        .await;
+
+                    match res {
+                        Ok(tx) => info!(
+                            "commitment submitted for transfer_id={} tx_hash={:#x}",
+                            transaction.id, tx.transaction_hash
+                        ),
+                        Err(e) => warn!(
+                            "error when submitting commitment for transfer_id={} error={e}",
+                            transaction.id
+                        ),
+                    }
+                }
+
+                Ok(Response::new(transfer_credits::Response {
+                    transaction: Some(transaction),
+                }))
+            }
+            // raised by the transfer itself when the balance changed in between
+            Err(ClientError::SupabaseError(e)) if e.code == INSUFFICIENT_CREDITS => {
+                Err(Status::failed_precondition("insufficient credits"))
+            }
+            Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
+        }
+    }
+
+    // CONDITIONS :
+    // reporter is the authenticated caller
+    // a rating is identified as `<request_id>:<rating_for>`
+    async fn report(
+        &self,
+        request: Request<report::Request>,
+    ) -> Result<Response<report::Response>> {
+        let reporter = AuthenticatedUser::of(&request)?.id.clone();
+
+        let report::Request {
+            target_type,
+            target_id,
+            reason,
+        } = request.into_inner();
+
+        let target_type = ReportTarget::from_i32(target_type)
+            .ok_or_else(|| Status::invalid_argument("invalid report target type"))?;
+
+        if target_id.is_empty() {
+            return Err(Status::invalid_argument(error_messages::MISSING_ARGUMENT));
+        }
+
+        if target_type == ReportTarget::Rating && !target_id.contains(':') {
+            return Err(Status::invalid_argument(
+                "a rating is reported as `<request_id>:<rating_for>`",
+            ));
+        }
+
+        if target_type == ReportTarget::Profile && target_id == reporter {
+            return Err(Status::invalid_argument("cannot report yourself"));
+        }
+
+        if reason.trim().is_empty() || reason.chars().count() > MAX_REPORT_REASON_LENGTH {
+            return Err(Status::invalid_argument(format!(
+                "reason must be between 1 and {MAX_REPORT_REASON_LENGTH} characters"
+            )));
+        }
+
+        let res = self
+            .moderation
+            .create_report(reporter, target_type, target_id, reason)
+            .await;
+
+        match res {
+            Ok(value) => Ok(Response::new(report::Response {
+                report: Some(value),
+            })),
             Err(ClientError::SupabaseError(e)) => Err(Status::unknown(e.to_string())),
-            Err(ClientError::InternalError(e)) => Err(Status::internal(e.to_string())),
+            Err(ClientError::InternalError(e)) => Err(e.into()),
         }
     }
 }
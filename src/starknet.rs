@@ -1,3 +1,4 @@
 pub mod admin_account;
 pub mod budi_core_contract;
+pub mod confirmations;
 pub mod provider;
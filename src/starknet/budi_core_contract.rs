@@ -1,4 +1,5 @@
-use super::admin_account::AdminAccount;
+use super::{admin_account::AdminAccount, confirmations};
+use crate::{layers::logger, metrics};
 
 use starknet::{
     accounts::Call,
@@ -9,7 +10,8 @@ use starknet::{
     macros::selector,
 };
 
-use color_eyre::Result;
+use color_eyre::{eyre::eyre, Result};
+use tracing::{info_span, Instrument};
 
 #[allow(unused)]
 #[derive(Debug)]
@@ -45,12 +47,11 @@ impl BudiCore {
         amount: f32,
         timestamp: impl AsRef<str>,
     ) -> Result<AddTransactionResult> {
-        let amount = (amount * 1000000000000000000f32) as u128;
-        let amount = FieldElement::from_dec_str(&amount.to_string())?;
+        let amount = to_credit_amount(amount)?;
 
-        let res = self
-            .account
-            .execute(&[Call {
+        self.submit(
+            "service_request",
+            Call {
                 to: self.contract_address,
                 selector: selector!("commit_service_request"),
                 calldata: vec![
@@ -61,10 +62,89 @@ impl BudiCore {
                     // should convert into unix timestamp but im too lazy
                     starknet_keccak(timestamp.as_ref().as_bytes()),
                 ],
-            }])
-            .send()
-            .await
-            .unwrap();
+            },
+        )
+        .await
+    }
+
+    /// Commits the corrected payment of a service request whose payment was
+    /// changed after its original commitment, e.g. when a dispute is resolved.
+    pub async fn commit_payment_correction(
+        &self,
+        request_id: impl AsRef<str>,
+        requestor: impl AsRef<str>,
+        provider: impl AsRef<str>,
+        amount: f32,
+        timestamp: impl AsRef<str>,
+    ) -> Result<AddTransactionResult> {
+        let amount = to_credit_amount(amount)?;
+
+        self.submit(
+            "payment_correction",
+            Call {
+                to: self.contract_address,
+                selector: selector!("commit_payment_correction"),
+                calldata: vec![
+                    starknet_keccak(request_id.as_ref().as_bytes()),
+                    starknet_keccak(requestor.as_ref().as_bytes()),
+                    starknet_keccak(provider.as_ref().as_bytes()),
+                    amount,
+                    starknet_keccak(timestamp.as_ref().as_bytes()),
+                ],
+            },
+        )
+        .await
+    }
+
+    pub async fn commit_credit_transfer(
+        &self,
+        transaction_id: impl AsRef<str>,
+        sender: impl AsRef<str>,
+        recipient: impl AsRef<str>,
+        amount: f32,
+        timestamp: impl AsRef<str>,
+    ) -> Result<AddTransactionResult> {
+        let amount = to_credit_amount(amount)?;
+
+        self.submit(
+            "credit_transfer",
+            Call {
+                to: self.contract_address,
+                selector: selector!("commit_credit_transfer"),
+                calldata: vec![
+                    starknet_keccak(transaction_id.as_ref().as_bytes()),
+                    starknet_keccak(sender.as_ref().as_bytes()),
+                    starknet_keccak(recipient.as_ref().as_bytes()),
+                    amount,
+                    starknet_keccak(timestamp.as_ref().as_bytes()),
+                ],
+            },
+        )
+        .await
+    }
+
+    // Every commitment goes through here, so that its outcome is counted and
+    // its confirmation tracked. The sequencer gateway provider can't add
+    // headers to its calls, so the id of the request that made the commitment
+    // is only kept on its span and on the logs of its confirmation.
+    async fn submit(&self, kind: &str, call: Call) -> Result<AddTransactionResult> {
+        let request_id = logger::current_request_id();
+        let span = info_span!(
+            "starknet.commit",
+            otel.kind = "client",
+            kind,
+            request_id = request_id.as_deref()
+        );
+        let res = self.account.execute(&[call]).send().instrument(span).await;
+
+        let outcome = if res.is_ok() { "submitted" } else { "failed" };
+        metrics::STARKNET_COMMITMENTS
+            .with_label_values(&[kind, outcome])
+            .inc();
+
+        let res = res.map_err(|e| eyre!(e.to_string()))?;
+        confirmations::track(res.transaction_hash, request_id);
+
         Ok(res)
     }
 
@@ -87,6 +167,12 @@ impl BudiCore {
     }
 }
 
+// credits are stored on-chain with 18 decimals
+fn to_credit_amount(amount: f32) -> Result<FieldElement> {
+    let amount = (amount * 1000000000000000000f32) as u128;
+    Ok(FieldElement::from_dec_str(&amount.to_string())?)
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -0,0 +1,63 @@
+//! Commitments submitted to StarkNet that haven't been accepted or rejected
+//! yet. They are only tracked in memory, so a restart forgets them.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use once_cell::sync::Lazy;
+use starknet::{
+    core::types::{FieldElement, TransactionStatus},
+    providers::Provider,
+};
+use tracing::warn;
+
+use super::provider::StarkNetProvider;
+
+// the transaction hash of each commitment, with the id of the request that
+// submitted it
+static AWAITING: Lazy<Mutex<HashMap<FieldElement, Option<String>>>> = Lazy::new(Default::default);
+
+pub fn track(transaction_hash: FieldElement, request_id: Option<String>) {
+    AWAITING
+        .lock()
+        .unwrap()
+        .insert(transaction_hash, request_id);
+}
+
+/// Checks the status of every awaiting commitment, forgets those that are
+/// final, and returns how many are still awaiting.
+pub async fn refresh() -> usize {
+    let awaiting: Vec<(FieldElement, Option<String>)> = AWAITING
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(hash, request_id)| (*hash, request_id.clone()))
+        .collect();
+
+    if awaiting.is_empty() {
+        return 0;
+    }
+
+    let provider = StarkNetProvider::new().provider;
+
+    for (hash, request_id) in awaiting {
+        let request_id = request_id.as_deref().unwrap_or("-");
+
+        match provider.get_transaction_status(hash).await {
+            Ok(info) => match info.status {
+                TransactionStatus::AcceptedOnL2 | TransactionStatus::AcceptedOnL1 => {
+                    AWAITING.lock().unwrap().remove(&hash);
+                }
+                TransactionStatus::Rejected => {
+                    warn!("commitment rejected tx_hash={hash:#x} request_id={request_id}");
+                    AWAITING.lock().unwrap().remove(&hash);
+                }
+                _ => {}
+            },
+            Err(e) => warn!(
+                "unable to get transaction status tx_hash={hash:#x} request_id={request_id} error={e}"
+            ),
+        }
+    }
+
+    AWAITING.lock().unwrap().len()
+}
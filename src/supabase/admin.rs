@@ -0,0 +1,146 @@
+use crate::proto::{
+    admin::FlaggedItem, servicerequest::ServiceRequestData, user::CreditTransaction,
+};
+use crate::supabase::{
+    self, decode, rpc::AdminRpc, service_request, user, ClientError, Execute, Schema,
+};
+
+use postgrest::Builder;
+use serde::Serialize;
+use serde_json::json;
+
+const TABLE: &str = "admin_actions";
+
+/// Actions taken through the `Admin` service. Every one of them is recorded
+/// in `admin_actions` with the admin who took it and their reason.
+#[derive(Default)]
+pub struct AdminClient {
+    client: supabase::Client,
+}
+
+#[tonic::async_trait]
+impl Schema for AdminClient {
+    type Method = AdminRpc;
+    const TABLE: &'static str = TABLE;
+
+    fn table(&self) -> Builder {
+        self.client.from(TABLE)
+    }
+
+    async fn rpc<T: Into<String> + std::marker::Send>(
+        &self,
+        method: Self::Method,
+        params: T,
+    ) -> Result<reqwest::Response, ClientError> {
+        self.client.rpc(method, params).await
+    }
+}
+
+impl AdminClient {
+    pub fn new() -> Self {
+        Self {
+            client: supabase::Client::new(),
+        }
+    }
+
+    pub async fn record_action<T, U, V, W>(
+        &self,
+        actor: T,
+        action: U,
+        target: V,
+        reason: W,
+    ) -> Result<(), ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+        W: Serialize,
+    {
+        self.rpc(
+            AdminRpc::RecordAction,
+            json!({
+                "_actor": actor,
+                "_action": action,
+                "_target": target,
+                "_reason": reason,
+            })
+            .to_string(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Cancels the request whatever its state and whoever is asking.
+    pub async fn force_cancel<T, U, V>(
+        &self,
+        request_id: T,
+        actor: U,
+        reason: V,
+    ) -> Result<ServiceRequestData, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+    {
+        let res = self
+            .rpc(
+                AdminRpc::ForceCancel,
+                json!({
+                    "_request_id": &request_id,
+                    "_actor": actor,
+                    "_reason": reason,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        // the hold on the requestor's credits is released
+        service_request::invalidate(&request_id);
+        user::invalidate_profiles();
+
+        decode::<ServiceRequestData>(res).await
+    }
+
+    /// Adds `amount` credits to the user, or removes them if it's negative.
+    pub async fn adjust_credits<T, U, V>(
+        &self,
+        user_id: T,
+        amount: f32,
+        actor: U,
+        reason: V,
+    ) -> Result<CreditTransaction, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+    {
+        let res = self
+            .rpc(
+                AdminRpc::AdjustCredits,
+                json!({
+                    "_user_id": &user_id,
+                    "_amount": amount,
+                    "_actor": actor,
+                    "_reason": reason,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        user::invalidate_profile(&user_id);
+
+        decode::<CreditTransaction>(res).await
+    }
+
+    /// Content flagged for review, most recent first.
+    pub async fn get_flagged(&self) -> Result<Vec<FlaggedItem>, ClientError> {
+        let res = self
+            .client
+            .from("flagged_content")
+            .order("flagged_at.desc")
+            .send("flagged_content")
+            .await?;
+
+        decode::<Vec<FlaggedItem>>(res).await
+    }
+}
@@ -1,8 +1,10 @@
-use reqwest::StatusCode;
+use reqwest::{RequestBuilder, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 use super::{ClientError, InternalErrorKind, PostgrestError};
+use crate::layers::logger;
 
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct SignUpResponse {
@@ -39,6 +41,33 @@ pub struct UserIdentity {
     pub updated_at: Option<String>,
 }
 
+/// The user a GoTrue access token belongs to.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub email: Option<String>,
+    pub role: Option<String>,
+    pub app_metadata: Value,
+    /// Set by [`AuthClient::ban_user`], as an RFC 3339 timestamp.
+    #[serde(default)]
+    pub banned_until: Option<String>,
+}
+
+impl User {
+    /// Whether the user is banned at `now`. A ban that can't be read is taken
+    /// as still running, rather than letting a suspended user through.
+    pub fn is_banned_at(&self, now: OffsetDateTime) -> bool {
+        match self.banned_until.as_deref().filter(|b| !b.is_empty()) {
+            Some(until) => match OffsetDateTime::parse(until, &Rfc3339) {
+                Ok(until) => until > now,
+                Err(_) => true,
+            },
+            None => false,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct AuthClient {
     client: reqwest::Client,
 }
@@ -50,6 +79,14 @@ impl AuthClient {
         }
     }
 
+    // Tags the call with the id of the RPC being handled, if any.
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        match logger::current_request_id() {
+            Some(id) => builder.header(logger::REQUEST_ID_HEADER, id),
+            None => builder,
+        }
+    }
+
     pub async fn sign_up<T, U>(&self, email: T, password: U) -> Result<SignUpResponse, ClientError>
     where
         T: Serialize,
@@ -60,8 +97,7 @@ impl AuthClient {
         let url = format!("{url}/signup");
 
         let res = self
-            .client
-            .post(url)
+            .request(self.client.post(url))
             .header("apikey", apikey)
             .json(&json!({
                 "email": email,
@@ -95,4 +131,125 @@ impl AuthClient {
             Err(ClientError::SupabaseError(err))
         }
     }
+
+    /// Returns the user of `access_token`, or `None` if GoTrue rejects the
+    /// token (e.g. it is expired or malformed).
+    pub async fn get_user(&self, access_token: &str) -> Result<Option<User>, ClientError> {
+        let apikey = dotenv::var("SUPABASE_API_KEY").expect("missing supabase apikey");
+        let url = dotenv::var("SUPABASE_AUTH_ENDPOINT").expect("missing supabase auth endpoint");
+        let url = format!("{url}/user");
+
+        let res = self
+            .request(self.client.get(url))
+            .header("apikey", apikey)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|e| {
+                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
+            })?;
+
+        if res.status().is_success() {
+            let user = res.json::<User>().await.map_err(|e| {
+                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
+            })?;
+
+            Ok(Some(user))
+        } else if res.status().is_client_error() {
+            Ok(None)
+        } else {
+            let err = res.text().await.map_err(|e| {
+                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
+            })?;
+
+            Err(ClientError::InternalError(InternalErrorKind::RequestError(
+                err,
+            )))
+        }
+    }
+
+    /// Bans `user_id` for `duration` (e.g. `24h`) through the GoTrue admin
+    /// API, or lifts the ban when `duration` is `none`. A banned user can't
+    /// sign in or refresh their session, and the access tokens they still
+    /// hold are rejected by `AuthenticatedUser::from_token`.
+    pub async fn ban_user(&self, user_id: &str, duration: &str) -> Result<(), ClientError> {
+        let apikey = dotenv::var("SUPABASE_API_KEY").expect("missing supabase apikey");
+        let url = dotenv::var("SUPABASE_AUTH_ENDPOINT").expect("missing supabase auth endpoint");
+        let url = format!("{url}/admin/users/{user_id}");
+
+        let res = self
+            .request(self.client.put(url))
+            .header("apikey", &apikey)
+            .bearer_auth(&apikey)
+            .json(&json!({ "ban_duration": duration }))
+            .send()
+            .await
+            .map_err(|e| {
+                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
+            })?;
+
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            let err = res.text().await.map_err(|e| {
+                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
+            })?;
+
+            Err(ClientError::InternalError(InternalErrorKind::RequestError(
+                err,
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    const NOW: OffsetDateTime = datetime!(2023-03-01 12:00 UTC);
+
+    fn banned_until(value: Option<&str>) -> User {
+        User {
+            banned_until: value.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn not_banned_without_a_ban() {
+        assert!(!banned_until(None).is_banned_at(NOW));
+        assert!(!banned_until(Some("")).is_banned_at(NOW));
+    }
+
+    #[test]
+    fn banned_until_the_ban_ends() {
+        let user = banned_until(Some("2023-03-01T12:30:00Z"));
+
+        assert!(user.is_banned_at(NOW));
+        assert!(!user.is_banned_at(datetime!(2023-03-01 12:30 UTC)));
+    }
+
+    #[test]
+    fn not_banned_after_the_ban_ended() {
+        assert!(!banned_until(Some("2023-02-28T12:00:00.123456Z")).is_banned_at(NOW));
+    }
+
+    #[test]
+    fn banned_when_the_ban_cannot_be_read() {
+        assert!(banned_until(Some("tomorrow")).is_banned_at(NOW));
+    }
+
+    #[test]
+    fn reads_the_ban_from_gotrue() {
+        let user: User = serde_json::from_value(json!({
+            "id": "user",
+            "app_metadata": {},
+            "banned_until": "2023-03-02T00:00:00+01:00",
+        }))
+        .unwrap();
+
+        assert!(user.is_banned_at(NOW));
+    }
 }
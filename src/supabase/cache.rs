@@ -0,0 +1,138 @@
+//! In-process read-through caches for the lookups the app makes constantly,
+//! such as profiles and service requests. Entries expire after
+//! `SUPABASE_CACHE_TTL_SECS` (default 30, `0` disables caching) and each cache
+//! keeps at most `SUPABASE_CACHE_SIZE` entries, evicting the least recently
+//! used. The clients drop the entries their writes make stale.
+
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::ClientError;
+use crate::metrics;
+
+const DEFAULT_TTL_SECS: u64 = 30;
+const DEFAULT_SIZE: usize = 10_000;
+
+struct Entries<V> {
+    values: LruCache<String, (Instant, V)>,
+    // Bumped on every invalidation, so that a value fetched before it isn't
+    // stored after it.
+    generation: u64,
+}
+
+pub(super) struct Cache<V> {
+    name: &'static str,
+    ttl: Duration,
+    entries: Mutex<Entries<V>>,
+}
+
+impl<V: Clone> Cache<V> {
+    /// `name` identifies the cache in the metrics, e.g. `users.get_profile`.
+    pub fn new(name: &'static str) -> Self {
+        let ttl = dotenv::var("SUPABASE_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TTL_SECS);
+
+        let size = dotenv::var("SUPABASE_CACHE_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .and_then(NonZeroUsize::new)
+            .unwrap_or(NonZeroUsize::new(DEFAULT_SIZE).unwrap());
+
+        Self {
+            name,
+            ttl: Duration::from_secs(ttl),
+            entries: Mutex::new(Entries {
+                values: LruCache::new(size),
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Returns the value cached for `key`, or fetches and caches it. Errors
+    /// are not cached.
+    pub async fn get_or_fetch<K, F, Fut>(&self, key: K, fetch: F) -> Result<V, ClientError>
+    where
+        K: Serialize,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, ClientError>>,
+    {
+        let key = match cache_key(key) {
+            Some(key) if !self.ttl.is_zero() => key,
+            _ => return fetch().await,
+        };
+
+        let generation = {
+            let mut entries = self.entries.lock().unwrap();
+
+            match entries.values.get(&key) {
+                Some((expires, value)) if *expires > Instant::now() => {
+                    let value = value.clone();
+                    drop(entries);
+                    self.record("hit");
+                    return Ok(value);
+                }
+                Some(_) => {
+                    entries.values.pop(&key);
+                }
+                None => {}
+            }
+
+            entries.generation
+        };
+
+        self.record("miss");
+
+        let value = fetch().await?;
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation == generation {
+            entries
+                .values
+                .put(key, (Instant::now() + self.ttl, value.clone()));
+        }
+
+        Ok(value)
+    }
+
+    pub fn invalidate<K: Serialize>(&self, key: K) {
+        let mut entries = self.entries.lock().unwrap();
+        match cache_key(key) {
+            Some(key) => {
+                entries.values.pop(&key);
+            }
+            None => entries.values.clear(),
+        }
+        entries.generation += 1;
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.values.clear();
+        entries.generation += 1;
+    }
+
+    fn record(&self, result: &str) {
+        metrics::SUPABASE_CACHE
+            .with_label_values(&[self.name, result])
+            .inc();
+    }
+}
+
+// Ids are passed around as any `Serialize`, so strings are unquoted to give
+// `"id"` and `String::from("id")` the same key.
+fn cache_key<K: Serialize>(key: K) -> Option<String> {
+    match serde_json::to_value(key).ok()? {
+        Value::String(s) => Some(s),
+        value => Some(value.to_string()),
+    }
+}
@@ -0,0 +1,65 @@
+use crate::supabase::{self, decode, parsing_error, ClientError, Execute};
+
+use postgrest::Builder;
+use serde::{Deserialize, Serialize};
+
+const TABLE: &str = "idempotency_keys";
+
+/// A row of `idempotency_keys`: the response returned for the first request
+/// made with `key`, and the fingerprint of that request's payload.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    pub fingerprint: String,
+    pub headers: Vec<(String, String)>,
+    /// Base64 encoded response body.
+    pub body: String,
+    pub trailers: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+pub struct IdempotencyClient {
+    client: supabase::Client,
+}
+
+impl IdempotencyClient {
+    pub fn new() -> Self {
+        Self {
+            client: supabase::Client::new(),
+        }
+    }
+
+    fn table(&self) -> Builder {
+        self.client.from(TABLE)
+    }
+
+    /// The record of `key`, if it was stored on or after `since`.
+    pub async fn get<T, U>(
+        &self,
+        key: T,
+        since: U,
+    ) -> Result<Option<IdempotencyRecord>, ClientError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let res = self
+            .table()
+            .eq("key", key)
+            .gte("created_at", since)
+            .send(TABLE)
+            .await?;
+
+        let values = decode::<Vec<IdempotencyRecord>>(res).await?;
+
+        Ok(values.into_iter().next())
+    }
+
+    pub async fn insert(&self, record: &IdempotencyRecord) -> Result<(), ClientError> {
+        let body = serde_json::to_string(record).map_err(|e| parsing_error(e.to_string()))?;
+
+        let res = self.table().insert(body).send(TABLE).await?;
+
+        decode::<Vec<IdempotencyRecord>>(res).await.map(|_| ())
+    }
+}
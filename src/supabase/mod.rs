@@ -1,20 +1,35 @@
+pub mod admin;
 pub mod auth;
+mod cache;
+pub mod idempotency;
+pub mod moderation;
+pub mod organisation;
 pub mod rating;
 pub(self) mod rpc;
+pub mod series;
 pub mod service_request;
+mod upstream;
 pub mod user;
 
 use core::fmt;
 use postgrest::{Builder, Postgrest};
 use reqwest::Response;
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+use serde_json::Value;
+use tracing::{field, info_span, Instrument};
 
 use self::rpc::RpcMethod;
+use crate::{layers::logger, metrics};
 
 #[derive(Debug)]
 pub enum InternalErrorKind {
     ParsingError(String),
     RequestError(String),
+    /// Supabase couldn't be reached, or is failing fast while it is down.
+    Unavailable(String),
 }
 
 impl std::error::Error for InternalErrorKind {}
@@ -24,10 +39,26 @@ impl fmt::Display for InternalErrorKind {
         match self {
             InternalErrorKind::ParsingError(s) => write!(f, "parsing error : {s}"),
             InternalErrorKind::RequestError(s) => write!(f, "request error : {s}"),
+            InternalErrorKind::Unavailable(s) => write!(f, "unavailable : {s}"),
         }
     }
 }
 
+impl From<&InternalErrorKind> for tonic::Status {
+    fn from(e: &InternalErrorKind) -> Self {
+        match e {
+            InternalErrorKind::Unavailable(_) => tonic::Status::unavailable(e.to_string()),
+            _ => tonic::Status::internal(e.to_string()),
+        }
+    }
+}
+
+impl From<InternalErrorKind> for tonic::Status {
+    fn from(e: InternalErrorKind) -> Self {
+        Self::from(&e)
+    }
+}
+
 #[derive(Debug)]
 pub enum ClientError {
     SupabaseError(PostgrestError),
@@ -95,21 +126,42 @@ impl Client {
         T: rpc::RpcMethod,
         U: Into<String>,
     {
-        let res = self
-            .postgrest
-            .rpc(function.name(), params)
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
+        let timer = metrics::SUPABASE_DURATION
+            .with_label_values(&[function.name()])
+            .start_timer();
+
+        let span = info_span!(
+            "postgrest.rpc",
+            otel.kind = "client",
+            function = function.name(),
+            http.status_code = field::Empty,
+        );
+
+        let request = self.postgrest().rpc(function.name(), params).build();
+
+        let res = upstream::send(request, function.is_read())
+            .instrument(span.clone())
+            .await;
+
+        timer.observe_duration();
+
+        if let Ok(res) = &res {
+            span.record("http.status_code", res.status().as_u16());
+        }
+
+        let res = res.map_err(|e| {
+            metrics::SUPABASE_ERRORS
+                .with_label_values(&[function.name(), "request"])
+                .inc();
+            e
+        })?;
 
         if !res.status().is_success() {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
+            metrics::SUPABASE_ERRORS
+                .with_label_values(&[function.name(), "supabase"])
+                .inc();
 
-            Err(ClientError::SupabaseError(err))
+            Err(error(res).await)
         } else {
             Ok(res)
         }
@@ -119,18 +171,113 @@ impl Client {
     where
         T: AsRef<str>,
     {
-        self.postgrest.from(table)
+        self.postgrest().from(table)
+    }
+
+    // Tags outgoing calls with the id of the RPC being handled, so that they
+    // can be matched with the PostgREST logs.
+    fn postgrest(&self) -> Postgrest {
+        match logger::current_request_id() {
+            Some(id) => self
+                .postgrest
+                .clone()
+                .insert_header(logger::REQUEST_ID_HEADER, id),
+            None => self.postgrest.clone(),
+        }
+    }
+}
+
+/// Checks that PostgREST can be reached and accepts the API key.
+pub async fn ping() -> Result<(), ClientError> {
+    let res = Client::new()
+        .from("service_requests")
+        .select("id")
+        .limit(1)
+        .send("service_requests")
+        .await?;
+
+    decode::<IgnoredAny>(res).await.map(|_| ())
+}
+
+/// Decodes a PostgREST response: the body as `T` when the call succeeded, the
+/// [`PostgrestError`] it returned otherwise. Every response is read through
+/// here, so that failed calls are always reported as such rather than as a
+/// body that couldn't be parsed.
+async fn decode<T: DeserializeOwned>(res: Response) -> Result<T, ClientError> {
+    if res.status().is_success() {
+        res.json::<T>()
+            .await
+            .map_err(|e| parsing_error(e.to_string()))
+    } else {
+        Err(error(res).await)
+    }
+}
+
+async fn error(res: Response) -> ClientError {
+    match res.json::<PostgrestError>().await {
+        Ok(err) => ClientError::SupabaseError(err),
+        Err(e) => parsing_error(e.to_string()),
+    }
+}
+
+fn parsing_error(message: String) -> ClientError {
+    ClientError::InternalError(InternalErrorKind::ParsingError(message))
+}
+
+/// Restricts `builder` to the rows whose `columns` still hold the given
+/// values, so that an update merged into values read earlier doesn't undo a
+/// concurrent one. The columns are JSON columns, compared as `jsonb`.
+fn unchanged(builder: Builder, columns: &[(String, Value)]) -> Builder {
+    columns
+        .iter()
+        .fold(builder, |builder, (column, value)| match value {
+            Value::Null => builder.is(column, "null"),
+            value => builder.eq(column, value.to_string()),
+        })
+}
+
+/// Sends queries built from [`Client::from`] in a span named after the table,
+/// so that they show up in traces, with the retries and circuit breaker of
+/// [`upstream`].
+#[tonic::async_trait]
+trait Execute {
+    async fn send(self, table: &str) -> Result<Response, ClientError>;
+}
+
+#[tonic::async_trait]
+impl Execute for Builder {
+    async fn send(self, table: &str) -> Result<Response, ClientError> {
+        let span = info_span!(
+            "postgrest.query",
+            otel.kind = "client",
+            table,
+            http.status_code = field::Empty,
+        );
+
+        let request = self.build();
+        let read = upstream::is_read(&request);
+
+        let res = upstream::send(request, read)
+            .instrument(span.clone())
+            .await?;
+
+        span.record("http.status_code", res.status().as_u16());
+        Ok(res)
     }
 }
 
 // what to have in the `schema` trait
 // - table() - to access the schema postgres table
 // - rpc() - to access rpc methods related to that schema (based on the naming of the rpc methods)
+// - select/insert/update/delete - typed queries on that table, decoded with `decode`
 
 #[tonic::async_trait]
 trait Schema {
     type Method: RpcMethod;
 
+    /// The table queried by [`Schema::table`], named in the traces.
+    const TABLE: &'static str;
+
     fn table(&self) -> Builder;
 
     async fn rpc<T: Into<String> + std::marker::Send>(
@@ -138,4 +285,58 @@ trait Schema {
         method: Self::Method,
         params: T,
     ) -> Result<Response, ClientError>;
+
+    /// The rows matched by `query`, which is built from [`Schema::table`].
+    async fn select<T>(&self, query: Builder) -> Result<Vec<T>, ClientError>
+    where
+        T: DeserializeOwned + Send,
+    {
+        decode(query.send(Self::TABLE).await?).await
+    }
+
+    /// Number of rows matched by `query`, which is built from
+    /// [`Schema::table`]. PostgREST counts them and sends the total in the
+    /// `Content-Range` header, without any row.
+    async fn count(&self, query: Builder) -> Result<usize, ClientError> {
+        let res = query.exact_count().limit(0).send(Self::TABLE).await?;
+
+        if !res.status().is_success() {
+            return Err(error(res).await);
+        }
+
+        // the range is `*/<total>` when no row is returned
+        res.headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit_once('/'))
+            .and_then(|(_, total)| total.parse().ok())
+            .ok_or_else(|| parsing_error(String::from("missing count in Content-Range")))
+    }
+
+    /// Inserts the JSON `body`, a row or an array of rows, and returns the
+    /// inserted rows.
+    async fn insert<T, B>(&self, body: B) -> Result<Vec<T>, ClientError>
+    where
+        T: DeserializeOwned + Send,
+        B: Into<String> + Send,
+    {
+        decode(self.table().insert(body).send(Self::TABLE).await?).await
+    }
+
+    /// Applies the JSON `body` to the rows matched by `query` and returns
+    /// them as updated.
+    async fn update<T, B>(&self, query: Builder, body: B) -> Result<Vec<T>, ClientError>
+    where
+        T: DeserializeOwned + Send,
+        B: Into<String> + Send,
+    {
+        decode(query.update(body).send(Self::TABLE).await?).await
+    }
+
+    /// Deletes the rows matched by `query`.
+    async fn delete(&self, query: Builder) -> Result<(), ClientError> {
+        decode::<IgnoredAny>(query.delete().send(Self::TABLE).await?)
+            .await
+            .map(|_| ())
+    }
 }
@@ -0,0 +1,124 @@
+use crate::proto::moderation::{ModerationAction, Report, ReportTarget};
+use crate::supabase::{
+    self, decode, rpc::ModerationRpc, service_request, user, ClientError, Schema,
+};
+
+use postgrest::Builder;
+use serde::Serialize;
+use serde_json::json;
+
+const TABLE: &str = "reports";
+
+#[derive(Default)]
+pub struct ModerationClient {
+    client: supabase::Client,
+}
+
+#[tonic::async_trait]
+impl Schema for ModerationClient {
+    type Method = ModerationRpc;
+    const TABLE: &'static str = TABLE;
+
+    fn table(&self) -> Builder {
+        self.client.from(TABLE)
+    }
+
+    async fn rpc<T: Into<String> + std::marker::Send>(
+        &self,
+        method: Self::Method,
+        params: T,
+    ) -> Result<reqwest::Response, ClientError> {
+        self.client.rpc(method, params).await
+    }
+}
+
+impl ModerationClient {
+    pub fn new() -> Self {
+        Self {
+            client: supabase::Client::new(),
+        }
+    }
+
+    /// Files a report. The database resolves who owns the target, and fails
+    /// if the target doesn't exist or the reporter already has an open
+    /// report on it.
+    pub async fn create_report<T, U, V>(
+        &self,
+        reporter: T,
+        target_type: ReportTarget,
+        target_id: U,
+        reason: V,
+    ) -> Result<Report, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+    {
+        let res = self
+            .rpc(
+                ModerationRpc::CreateReport,
+                json!({
+                    "_reporter": reporter,
+                    "_target_type": target_type as i32,
+                    "_target_id": target_id,
+                    "_reason": reason,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        decode::<Report>(res).await
+    }
+
+    pub async fn get_by_id<T: AsRef<str>>(&self, id: T) -> Result<Option<Report>, ClientError> {
+        self.select(self.table().eq("id", id))
+            .await
+            .map(|values| values.into_iter().next())
+    }
+
+    /// Open reports, oldest first.
+    pub async fn get_queue(&self) -> Result<Vec<Report>, ClientError> {
+        self.select(self.table().eq("status", "0").order("created_at.asc"))
+            .await
+    }
+
+    /// Closes the report and applies `action` to its target. Hiding the
+    /// content, warning its owner and recording the admin action happen in
+    /// the same transaction. Suspensions go through GoTrue and must be made
+    /// before calling this.
+    pub async fn resolve_report<T, U, V>(
+        &self,
+        report_id: T,
+        moderator: U,
+        action: ModerationAction,
+        note: V,
+    ) -> Result<Report, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+    {
+        let res = self
+            .rpc(
+                ModerationRpc::ResolveReport,
+                json!({
+                    "_report_id": report_id,
+                    "_moderator": moderator,
+                    "_action": action as i32,
+                    "_note": note,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        // Hidden content must stop being served right away. Reports are rare
+        // enough that the caches are simply emptied rather than matched
+        // against the target.
+        if action == ModerationAction::Hide {
+            service_request::invalidate_all();
+            user::invalidate_profiles();
+        }
+
+        decode::<Report>(res).await
+    }
+}
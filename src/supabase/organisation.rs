@@ -0,0 +1,211 @@
+use crate::proto::organisation::{
+    create::NewOrganisationData, get_credit_balance, Member, MemberRole, OrganisationData,
+};
+use crate::supabase::{self, decode, rpc::OrganisationRpc, ClientError, Execute, Schema};
+
+use postgrest::Builder;
+use serde::Serialize;
+use serde_json::json;
+
+const TABLE: &str = "organisations";
+const MEMBERS_TABLE: &str = "organisation_members";
+
+#[derive(Default)]
+pub struct OrganisationClient {
+    client: supabase::Client,
+}
+
+#[tonic::async_trait]
+impl Schema for OrganisationClient {
+    type Method = OrganisationRpc;
+    const TABLE: &'static str = TABLE;
+
+    fn table(&self) -> Builder {
+        self.client.from(TABLE)
+    }
+
+    async fn rpc<T: Into<String> + std::marker::Send>(
+        &self,
+        method: Self::Method,
+        params: T,
+    ) -> Result<reqwest::Response, ClientError> {
+        self.client.rpc(method, params).await
+    }
+}
+
+impl OrganisationClient {
+    pub fn new() -> Self {
+        Self {
+            client: supabase::Client::new(),
+        }
+    }
+
+    /// Creates the organisation with `creator` as its first admin.
+    pub async fn create<T>(
+        &self,
+        creator: T,
+        data: NewOrganisationData,
+    ) -> Result<OrganisationData, ClientError>
+    where
+        T: Serialize,
+    {
+        let res = self
+            .rpc(
+                OrganisationRpc::Create,
+                json!({
+                    "_creator": creator,
+                    "_organisation": data,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        decode::<OrganisationData>(res).await
+    }
+
+    pub async fn get_by_id<T: AsRef<str>>(
+        &self,
+        id: T,
+    ) -> Result<Option<OrganisationData>, ClientError> {
+        self.select(self.table().eq("id", id))
+            .await
+            .map(|values| values.into_iter().next())
+    }
+
+    /// The shared balance, funded by transfers to the organisation's pool.
+    pub async fn get_credit_balance<T: Serialize>(
+        &self,
+        organisation_id: T,
+    ) -> Result<get_credit_balance::Response, ClientError> {
+        let res = self
+            .rpc(
+                OrganisationRpc::GetCreditBalance,
+                json!({ "_organisation_id": organisation_id }).to_string(),
+            )
+            .await?;
+
+        decode::<get_credit_balance::Response>(res).await
+    }
+
+    pub async fn get_members<T: AsRef<str>>(
+        &self,
+        organisation_id: T,
+    ) -> Result<Vec<Member>, ClientError> {
+        let res = self
+            .client
+            .from(MEMBERS_TABLE)
+            .eq("organisation_id", organisation_id)
+            .send(MEMBERS_TABLE)
+            .await?;
+
+        decode::<Vec<Member>>(res).await
+    }
+
+    pub async fn get_member<T, U>(
+        &self,
+        organisation_id: T,
+        user_id: U,
+    ) -> Result<Option<Member>, ClientError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let res = self
+            .client
+            .from(MEMBERS_TABLE)
+            .eq("organisation_id", organisation_id)
+            .eq("user_id", user_id)
+            .send(MEMBERS_TABLE)
+            .await?;
+
+        decode::<Vec<Member>>(res)
+            .await
+            .map(|values| values.into_iter().next())
+    }
+
+    /// Whether `user_id` can spend the credits of the organisation, which
+    /// only its admins can.
+    pub async fn can_spend<T, U>(&self, organisation_id: T, user_id: U) -> Result<bool, ClientError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let member = self.get_member(organisation_id, user_id).await?;
+
+        Ok(member.is_some_and(|m| m.role == MemberRole::Admin as i32))
+    }
+
+    pub async fn add_member<T, U>(
+        &self,
+        organisation_id: T,
+        user_id: U,
+        role: MemberRole,
+    ) -> Result<Member, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+    {
+        let res = self
+            .client
+            .from(MEMBERS_TABLE)
+            .insert(
+                json!({
+                    "organisation_id": organisation_id,
+                    "user_id": user_id,
+                    "role": role as i32,
+                })
+                .to_string(),
+            )
+            .send(MEMBERS_TABLE)
+            .await?;
+
+        decode::<Vec<Member>>(res)
+            .await
+            .map(|values| values.into_iter().next().unwrap_or_default())
+    }
+
+    pub async fn update_member_role<T, U>(
+        &self,
+        organisation_id: T,
+        user_id: U,
+        role: MemberRole,
+    ) -> Result<Member, ClientError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let res = self
+            .client
+            .from(MEMBERS_TABLE)
+            .eq("organisation_id", organisation_id)
+            .eq("user_id", user_id)
+            .update(json!({ "role": role as i32 }).to_string())
+            .send(MEMBERS_TABLE)
+            .await?;
+
+        decode::<Vec<Member>>(res)
+            .await
+            .map(|values| values.into_iter().next().unwrap_or_default())
+    }
+
+    pub async fn remove_member<T, U>(
+        &self,
+        organisation_id: T,
+        user_id: U,
+    ) -> Result<(), ClientError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let res = self
+            .client
+            .from(MEMBERS_TABLE)
+            .eq("organisation_id", organisation_id)
+            .eq("user_id", user_id)
+            .delete()
+            .send(MEMBERS_TABLE)
+            .await?;
+
+        decode::<Vec<Member>>(res).await.map(|_| ())
+    }
+}
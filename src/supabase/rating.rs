@@ -1,11 +1,19 @@
 use crate::proto::rating::{create::NewRatingData, RatingData};
-use crate::supabase::{
-    self, rpc::RatingRpc, ClientError, InternalErrorKind, PostgrestError, Schema,
-};
+use crate::supabase::{self, decode, rpc::RatingRpc, service_request, user, ClientError, Schema};
 
 use postgrest::Builder;
+use serde::Serialize;
 use serde_json::json;
 
+const TABLE: &str = "ratings";
+
+// Ratings count in the profile of the rated user and show in the request, so
+// both are dropped from the caches when a rating changes.
+fn invalidate(request_id: impl Serialize, rating_for: impl Serialize) {
+    service_request::invalidate(request_id);
+    user::invalidate_profile(rating_for);
+}
+
 #[derive(Default)]
 pub struct RatingClient {
     client: supabase::Client,
@@ -14,9 +22,10 @@ pub struct RatingClient {
 #[tonic::async_trait]
 impl Schema for RatingClient {
     type Method = RatingRpc;
+    const TABLE: &'static str = TABLE;
 
     fn table(&self) -> Builder {
-        self.client.from("ratings")
+        self.client.from(TABLE)
     }
 
     async fn rpc<T: Into<String> + std::marker::Send>(
@@ -59,11 +68,15 @@ impl RatingClient {
             )
             .await?;
 
-        let values = res.json::<Vec<RatingData>>().await.map_err(|e| {
-            ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-        })?;
+        let rating = decode::<Vec<RatingData>>(res)
+            .await?
+            .into_iter()
+            .next()
+            .unwrap_or_default();
 
-        Ok(values.into_iter().next().unwrap_or_default())
+        invalidate(&rating.request_id, &rating.rating_for);
+
+        Ok(rating)
     }
 
     pub async fn create_for_provider(
@@ -90,11 +103,15 @@ impl RatingClient {
             )
             .await?;
 
-        let values = res.json::<Vec<RatingData>>().await.map_err(|e| {
-            ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-        })?;
+        let rating = decode::<Vec<RatingData>>(res)
+            .await?
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+
+        invalidate(&rating.request_id, &rating.rating_for);
 
-        Ok(values.into_iter().next().unwrap_or_default())
+        Ok(rating)
     }
 
     pub async fn get<T, U>(&self, column: T, filter: U) -> Result<Vec<RatingData>, ClientError>
@@ -102,56 +119,28 @@ impl RatingClient {
         T: AsRef<str>,
         U: AsRef<str>,
     {
-        let res = self
-            .table()
-            .eq(column, filter)
-            .execute()
+        self.select(self.table().eq(column, filter).is("hidden", "false"))
             .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        if res.status().is_success() {
-            let values = res.json::<Vec<RatingData>>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Ok(values)
-        } else {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Err(ClientError::SupabaseError(err))
-        }
+    }
+
+    /// The ratings of a request, including those hidden by a moderator.
+    pub async fn get_all_for_request<T: AsRef<str>>(
+        &self,
+        request_id: T,
+    ) -> Result<Vec<RatingData>, ClientError> {
+        self.select(self.table().eq("request_id", request_id)).await
     }
 
     pub async fn get_for_request<T: AsRef<str>>(
         &self,
         request_id: T,
     ) -> Result<Vec<RatingData>, ClientError> {
-        let res = self
-            .table()
-            .eq("request_id", request_id)
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        if res.status().is_success() {
-            let values = res.json::<Vec<RatingData>>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Ok(values)
-        } else {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Err(ClientError::SupabaseError(err))
-        }
+        self.select(
+            self.table()
+                .eq("request_id", request_id)
+                .is("hidden", "false"),
+        )
+        .await
     }
 
     pub async fn get_by_id<T, U>(
@@ -163,92 +152,72 @@ impl RatingClient {
         T: AsRef<str>,
         U: AsRef<str>,
     {
-        let res = self
-            .table()
-            .eq("request_id", request_id)
-            .eq("rating_for", rating_for)
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        if res.status().is_success() {
-            let values = res.json::<Vec<RatingData>>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Ok(values)
-        } else {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Err(ClientError::SupabaseError(err))
-        }
+        self.select(
+            self.table()
+                .eq("request_id", request_id)
+                .eq("rating_for", rating_for)
+                .is("hidden", "false"),
+        )
+        .await
     }
 
-    pub async fn update<T, U, V>(
+    /// Applies `body` to the rating and records the edit in the rating audit
+    /// trail. Both happen in the same transaction on the database side.
+    pub async fn update<T, U, V, W>(
         &self,
         request_id: T,
         rating_for: U,
-        body: V,
+        editor: V,
+        body: W,
     ) -> Result<Vec<RatingData>, ClientError>
     where
-        T: AsRef<str>,
-        U: AsRef<str>,
-        V: Into<String>,
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+        W: Serialize,
     {
         let res = self
-            .table()
-            .eq("request_id", request_id)
-            .eq("rating_for", rating_for)
-            .update(body)
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        if res.status().is_success() {
-            let values = res.json::<Vec<RatingData>>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Ok(values)
-        } else {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Err(ClientError::SupabaseError(err))
-        }
+            .rpc(
+                RatingRpc::Update,
+                json!({
+                    "_request_id": &request_id,
+                    "_rating_for": &rating_for,
+                    "_editor": editor,
+                    "_body": body,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        invalidate(&request_id, &rating_for);
+
+        decode::<Vec<RatingData>>(res).await
     }
 
-    pub async fn delete<T, U>(&self, request_id: T, rating_for: U) -> Result<(), ClientError>
+    /// Deletes the rating and records the deletion in the rating audit trail.
+    pub async fn delete<T, U, V>(
+        &self,
+        request_id: T,
+        rating_for: U,
+        editor: V,
+    ) -> Result<(), ClientError>
     where
-        T: AsRef<str>,
-        U: AsRef<str>,
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
     {
-        let res = self
-            .table()
-            .eq("request_id", request_id)
-            .eq("rating_for", rating_for)
-            .delete()
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        if !res.status().is_success() {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Err(ClientError::SupabaseError(err))
-        } else {
-            Ok(())
-        }
+        self.rpc(
+            RatingRpc::Delete,
+            json!({
+                "_request_id": &request_id,
+                "_rating_for": &rating_for,
+                "_editor": editor,
+            })
+            .to_string(),
+        )
+        .await?;
+
+        invalidate(&request_id, &rating_for);
+        Ok(())
     }
 }
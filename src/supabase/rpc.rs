@@ -3,6 +3,11 @@ use strum_macros::AsRefStr;
 
 pub trait RpcMethod {
     fn name(&self) -> &str;
+
+    /// Whether the function only reads data, and so can be retried.
+    fn is_read(&self) -> bool {
+        false
+    }
 }
 
 #[derive(AsRefStr, Debug)]
@@ -23,6 +28,22 @@ pub enum ServiceRequestRpc {
     GetById,
     #[strum(serialize = "servicerequests_getsummaryforuser")]
     GetSummaryForUser,
+    #[strum(serialize = "servicerequests_withdrawapplication")]
+    WithdrawApplication,
+    #[strum(serialize = "servicerequests_cancel")]
+    Cancel,
+    #[strum(serialize = "servicerequests_opendispute")]
+    OpenDispute,
+    #[strum(serialize = "servicerequests_resolvedispute")]
+    ResolveDispute,
+    #[strum(serialize = "servicerequests_confirmtimelog")]
+    ConfirmTimeLog,
+    #[strum(serialize = "servicerequests_expirepending")]
+    ExpirePending,
+    #[strum(serialize = "servicerequests_rejectunselectedapplicants")]
+    RejectUnselectedApplicants,
+    #[strum(serialize = "servicerequests_flagnoshows")]
+    FlagNoShows,
 }
 
 #[derive(AsRefStr, Debug)]
@@ -31,7 +52,8 @@ pub enum RatingRpc {
     CreateForProvider,
     #[strum(serialize = "ratings_createforrequestor")]
     CreateForRequestor,
-    #[allow(unused)]
+    #[strum(serialize = "ratings_update")]
+    Update,
     #[strum(serialize = "ratings_delete")]
     Delete,
 }
@@ -48,6 +70,50 @@ pub enum UserRpc {
     GetCreditBalance,
     #[strum(serialize = "users_gettransactionhistory")]
     GetTransactionHistory,
+    #[strum(serialize = "users_placecredithold")]
+    PlaceCreditHold,
+    #[strum(serialize = "users_releasecredithold")]
+    ReleaseCreditHold,
+    #[strum(serialize = "users_releasestaleholds")]
+    ReleaseStaleHolds,
+    #[strum(serialize = "users_transfercredits")]
+    TransferCredits,
+}
+
+#[derive(AsRefStr, Debug)]
+pub enum SeriesRpc {
+    #[strum(serialize = "series_deletependingoccurrences")]
+    DeletePendingOccurrences,
+    #[strum(serialize = "series_getoccurrencedates")]
+    GetOccurrenceDates,
+    #[strum(serialize = "series_createoccurrence")]
+    CreateOccurrence,
+}
+
+#[derive(AsRefStr, Debug)]
+pub enum OrganisationRpc {
+    #[strum(serialize = "organisations_create")]
+    Create,
+    #[strum(serialize = "organisations_getcreditbalance")]
+    GetCreditBalance,
+}
+
+#[derive(AsRefStr, Debug)]
+pub enum AdminRpc {
+    #[strum(serialize = "admin_forcecancel")]
+    ForceCancel,
+    #[strum(serialize = "admin_adjustcredits")]
+    AdjustCredits,
+    #[strum(serialize = "admin_recordaction")]
+    RecordAction,
+}
+
+#[derive(AsRefStr, Debug)]
+pub enum ModerationRpc {
+    #[strum(serialize = "moderation_createreport")]
+    CreateReport,
+    #[strum(serialize = "moderation_resolvereport")]
+    ResolveReport,
 }
 
 macro_rules! rpc_method {
@@ -58,8 +124,31 @@ macro_rules! rpc_method {
             }
         }
     };
+    ($rpc_enum:ty, reads = [$($read:ident),+]) => {
+        impl RpcMethod for $rpc_enum {
+            fn name(&self) -> &str {
+                self.as_ref()
+            }
+
+            fn is_read(&self) -> bool {
+                matches!(self, $(Self::$read)|+)
+            }
+        }
+    };
 }
 
-rpc_method!(ServiceRequestRpc);
+rpc_method!(ServiceRequestRpc, reads = [GetById, GetSummaryForUser]);
 rpc_method!(RatingRpc);
-rpc_method!(UserRpc);
+rpc_method!(
+    UserRpc,
+    reads = [
+        GetProfile,
+        CheckIfEmailExist,
+        GetCreditBalance,
+        GetTransactionHistory
+    ]
+);
+rpc_method!(SeriesRpc, reads = [GetOccurrenceDates]);
+rpc_method!(OrganisationRpc, reads = [GetCreditBalance]);
+rpc_method!(AdminRpc);
+rpc_method!(ModerationRpc);
@@ -0,0 +1,177 @@
+use crate::proto::servicerequest::{create::NewServiceRequestData, ServiceRequestData};
+use crate::supabase::{self, decode, rpc::SeriesRpc, service_request, ClientError, Schema};
+
+use postgrest::Builder;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const TABLE: &str = "service_request_series";
+
+/// A row of `service_request_series`. `template` is the request data every
+/// occurrence is created from, with the date replaced by the occurrence's.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Series {
+    pub id: String,
+    pub requestor: String,
+    pub template: Value,
+    pub recurrence: String,
+    pub starts_on: String,
+    pub materialized_until: Option<String>,
+    pub active: bool,
+}
+
+#[derive(Default)]
+pub struct SeriesClient {
+    client: supabase::Client,
+}
+
+#[tonic::async_trait]
+impl Schema for SeriesClient {
+    type Method = SeriesRpc;
+    const TABLE: &'static str = TABLE;
+
+    fn table(&self) -> Builder {
+        self.client.from(TABLE)
+    }
+
+    async fn rpc<T: Into<String> + std::marker::Send>(
+        &self,
+        method: Self::Method,
+        params: T,
+    ) -> Result<reqwest::Response, ClientError> {
+        self.client.rpc(method, params).await
+    }
+}
+
+impl SeriesClient {
+    pub fn new() -> Self {
+        Self {
+            client: supabase::Client::new(),
+        }
+    }
+
+    pub async fn create<T>(
+        &self,
+        requestor: T,
+        template: Value,
+        recurrence: &str,
+        starts_on: &str,
+    ) -> Result<Series, ClientError>
+    where
+        T: Serialize,
+    {
+        self.insert(
+            json!({
+                "requestor": requestor,
+                "template": template,
+                "recurrence": recurrence,
+                "starts_on": starts_on,
+                "active": true,
+            })
+            .to_string(),
+        )
+        .await
+        .map(|values| values.into_iter().next().unwrap_or_default())
+    }
+
+    pub async fn get_by_id<T: AsRef<str>>(&self, id: T) -> Result<Option<Series>, ClientError> {
+        self.select(self.table().eq("id", id))
+            .await
+            .map(|values| values.into_iter().next())
+    }
+
+    pub async fn get_active(&self) -> Result<Vec<Series>, ClientError> {
+        self.select(self.table().eq("active", "true")).await
+    }
+
+    pub async fn update<T: AsRef<str>>(&self, id: T, body: Value) -> Result<Series, ClientError> {
+        Schema::update(self, self.table().eq("id", id), body.to_string())
+            .await
+            .map(|values| values.into_iter().next().unwrap_or_default())
+    }
+
+    /// Deletes the occurrences of the series on or after `from` that nobody
+    /// has been selected for yet.
+    pub async fn delete_pending_occurrences<T, U>(
+        &self,
+        series_id: T,
+        from: U,
+    ) -> Result<(), ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+    {
+        self.rpc(
+            SeriesRpc::DeletePendingOccurrences,
+            json!({
+                "_series_id": series_id,
+                "_from": from,
+            })
+            .to_string(),
+        )
+        .await?;
+
+        service_request::invalidate_all();
+        Ok(())
+    }
+
+    /// Dates of the occurrences already created for the series on or after
+    /// `from`.
+    pub async fn get_occurrence_dates<T, U>(
+        &self,
+        series_id: T,
+        from: U,
+    ) -> Result<Vec<String>, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+    {
+        let res = self
+            .rpc(
+                SeriesRpc::GetOccurrenceDates,
+                json!({
+                    "_series_id": series_id,
+                    "_from": from,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        decode::<Vec<String>>(res).await
+    }
+
+    /// Creates a service request from `request_data` and links it to the
+    /// series as an occurrence, in a single transaction so that a request is
+    /// never left without its series.
+    ///
+    /// Only the database constraints apply, so the checks the `Create` RPC
+    /// makes are up to the caller, see `scheduler::series::materialise`.
+    pub async fn create_occurrence<T, U>(
+        &self,
+        series_id: T,
+        requestor: U,
+        request_data: NewServiceRequestData,
+    ) -> Result<ServiceRequestData, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+    {
+        let res = self
+            .rpc(
+                SeriesRpc::CreateOccurrence,
+                json!({
+                    "_series_id": series_id,
+                    "_requestor": requestor,
+                    "_request": request_data,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        service_request::invalidate_all();
+
+        let values = decode::<Vec<ServiceRequestData>>(res).await?;
+
+        Ok(values.into_iter().next().unwrap_or_default())
+    }
+}
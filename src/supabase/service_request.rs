@@ -1,11 +1,119 @@
-use crate::proto::servicerequest::{create, get_by_id, get_summary_for_user, ServiceRequestData};
+use crate::proto::servicerequest::{
+    create, get_by_id, get_summary_for_user, Dispute, ServiceRequestData, TimeEntry, TimeLog,
+    TimeLogConfirmation,
+};
 use crate::supabase::{
-    self, rpc::ServiceRequestRpc, ClientError, InternalErrorKind, PostgrestError, Schema,
+    self, cache::Cache, decode, rpc::ServiceRequestRpc, user, ClientError, Execute, Schema,
 };
 
+use core::fmt;
+use once_cell::sync::Lazy;
 use postgrest::Builder;
-use serde::Serialize;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+const TABLE: &str = "service_requests";
+
+static REQUESTS: Lazy<Cache<get_by_id::Response>> =
+    Lazy::new(|| Cache::new("servicerequests.get_by_id"));
+static SUMMARIES: Lazy<Cache<get_summary_for_user::Response>> =
+    Lazy::new(|| Cache::new("servicerequests.get_summary_for_user"));
+
+/// Drops the cached lookups a change to `request_id` makes stale. A request
+/// counts in the summaries of its requestor and of its applicants, so all of
+/// the summaries are dropped.
+pub(super) fn invalidate(request_id: impl Serialize) {
+    REQUESTS.invalidate(request_id);
+    SUMMARIES.clear();
+}
+
+/// Drops every cached lookup, for changes to requests that aren't known up
+/// front, e.g. the ones expired by the scheduler.
+pub(super) fn invalidate_all() {
+    REQUESTS.clear();
+    SUMMARIES.clear();
+}
+
+/// The values stored in the `state` column of `service_requests`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestState {
+    Pending = 0,
+    Accepted = 1,
+    Ongoing = 2,
+    Completed = 3,
+    Cancelled = 4,
+    Expired = 5,
+    NoShow = 6,
+}
+
+impl RequestState {
+    pub fn of(request: &ServiceRequestData) -> Option<Self> {
+        match request.state {
+            0 => Some(Self::Pending),
+            1 => Some(Self::Accepted),
+            2 => Some(Self::Ongoing),
+            3 => Some(Self::Completed),
+            4 => Some(Self::Cancelled),
+            5 => Some(Self::Expired),
+            6 => Some(Self::NoShow),
+            _ => None,
+        }
+    }
+}
+
+/// Returned by `servicerequests_resolvedispute`. `previous_payment` is the
+/// `actual_payment` of the request before the resolution was applied.
+#[derive(Debug, Default, Deserialize)]
+pub struct DisputeResolution {
+    pub dispute: Dispute,
+    pub request: ServiceRequestData,
+    pub previous_payment: f32,
+}
+
+/// An application of a provider to a service request.
+#[derive(Debug, Default, Deserialize)]
+pub struct Application {
+    pub request_id: String,
+    pub provider: String,
+}
+
+#[derive(Debug)]
+pub enum TimeLogError {
+    TimerRunning,
+    NoTimerRunning,
+    AlreadyConfirmed,
+    EmptyLog,
+    InvalidRange,
+    InFuture,
+    Overlapping,
+    InvalidTimestamp(String),
+    Client(ClientError),
+}
+
+impl std::error::Error for TimeLogError {}
+
+impl fmt::Display for TimeLogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeLogError::TimerRunning => write!(f, "a timer is already running"),
+            TimeLogError::NoTimerRunning => write!(f, "no timer is running"),
+            TimeLogError::AlreadyConfirmed => write!(f, "the time log is already confirmed"),
+            TimeLogError::EmptyLog => write!(f, "no time has been logged"),
+            TimeLogError::InvalidRange => write!(f, "an entry must end after it starts"),
+            TimeLogError::InFuture => write!(f, "an entry cannot end in the future"),
+            TimeLogError::Overlapping => write!(f, "the entry overlaps with a logged entry"),
+            TimeLogError::InvalidTimestamp(s) => write!(f, "invalid timestamp : {s}"),
+            TimeLogError::Client(e) => e.fmt(f),
+        }
+    }
+}
+
+impl From<ClientError> for TimeLogError {
+    fn from(e: ClientError) -> Self {
+        TimeLogError::Client(e)
+    }
+}
 
 #[derive(Default)]
 pub struct ServiceRequestClient {
@@ -15,9 +123,10 @@ pub struct ServiceRequestClient {
 #[tonic::async_trait]
 impl Schema for ServiceRequestClient {
     type Method = ServiceRequestRpc;
+    const TABLE: &'static str = TABLE;
 
     fn table(&self) -> Builder {
-        self.client.from("service_requests")
+        self.client.from(TABLE)
     }
 
     async fn rpc<T: Into<String> + std::marker::Send>(
@@ -36,6 +145,9 @@ impl ServiceRequestClient {
         }
     }
 
+    /// Creates a service request. When `organisation_id` is set, the request
+    /// is made on behalf of that organisation and its hold is placed on the
+    /// organisation's balance instead of the requestor's.
     pub async fn create<T>(
         &self,
         requestor: T,
@@ -55,13 +167,14 @@ impl ServiceRequestClient {
             )
             .await?;
 
-        let values = res.json::<Vec<ServiceRequestData>>().await.map_err(|e| {
-            ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-        })?;
+        SUMMARIES.clear();
+
+        let values = decode::<Vec<ServiceRequestData>>(res).await?;
 
         Ok(values.into_iter().next().unwrap_or_default())
     }
 
+    /// Requests hidden by a moderator are left out.
     pub async fn get<T, U>(
         &self,
         column: T,
@@ -71,79 +184,64 @@ impl ServiceRequestClient {
         T: AsRef<str>,
         U: AsRef<str>,
     {
-        let res = self
-            .table()
-            .eq(column, filter)
-            .execute()
+        self.select(self.table().eq(column, filter).is("hidden", "false"))
             .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        if res.status().is_success() {
-            let values = res.json::<Vec<ServiceRequestData>>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Ok(values)
-        } else {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Err(ClientError::SupabaseError(err))
-        }
     }
 
+    /// Like [`ServiceRequestClient::get`], but includes hidden requests. Meant
+    /// for lookups that aren't shown to users, such as permission checks.
+    pub async fn get_including_hidden<T, U>(
+        &self,
+        column: T,
+        filter: U,
+    ) -> Result<Vec<ServiceRequestData>, ClientError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        self.select(self.table().eq(column, filter)).await
+    }
+
+    /// Cached, see [`invalidate`].
     pub async fn get_by_id<T>(&self, request_id: T) -> Result<get_by_id::Response, ClientError>
     where
         T: Serialize,
     {
-        let res = self
-            .rpc(
-                ServiceRequestRpc::GetById,
-                json!({
-                    "_request_id": request_id,
-                })
-                .to_string(),
-            )
-            .await?;
-
-        let value = res.json::<get_by_id::Response>().await.map_err(|e| {
-            ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-        })?;
-
-        Ok(value)
+        REQUESTS
+            .get_or_fetch(&request_id, || async {
+                let res = self
+                    .rpc(
+                        ServiceRequestRpc::GetById,
+                        json!({
+                            "_request_id": &request_id,
+                        })
+                        .to_string(),
+                    )
+                    .await?;
+
+                decode::<get_by_id::Response>(res).await
+            })
+            .await
     }
 
-    pub async fn update<T, U>(&self, id: T, body: U) -> Result<Vec<ServiceRequestData>, ClientError>
+    /// Updates the request unless one of the `unchanged` columns was changed
+    /// since it was read, in which case nothing is returned.
+    pub async fn update<T, U>(
+        &self,
+        id: T,
+        body: U,
+        unchanged: &[(String, Value)],
+    ) -> Result<Vec<ServiceRequestData>, ClientError>
     where
         T: AsRef<str>,
         U: Into<String>,
     {
-        let res = self
-            .table()
-            .eq("id", id)
-            .update(body)
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        if res.status().is_success() {
-            let values = res.json::<Vec<ServiceRequestData>>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Ok(values)
-        } else {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Err(ClientError::SupabaseError(err))
-        }
+        let query = supabase::unchanged(self.table().eq("id", id.as_ref()), unchanged);
+        let values = Schema::update(self, query, body.into()).await?;
+
+        invalidate(id.as_ref());
+
+        Ok(values)
     }
 
     pub async fn delete<T>(&self, id: T) -> Result<(), ClientError>
@@ -152,9 +250,11 @@ impl ServiceRequestClient {
     {
         self.rpc(
             ServiceRequestRpc::Delete,
-            json!({ "_request_id": id }).to_string(),
+            json!({ "_request_id": &id }).to_string(),
         )
         .await?;
+
+        invalidate(&id);
         Ok(())
     }
 
@@ -166,12 +266,14 @@ impl ServiceRequestClient {
         self.rpc(
             ServiceRequestRpc::ApplyProvider,
             json!({
-                "_request_id": id,
+                "_request_id": &id,
                 "_provider": provider
             })
             .to_string(),
         )
         .await?;
+
+        invalidate(&id);
         Ok(())
     }
 
@@ -190,12 +292,14 @@ impl ServiceRequestClient {
             ServiceRequestRpc::SelectProvider,
             json!({
                 "_caller": user,
-                "_request_id": id,
+                "_request_id": &id,
                 "_provider": provider,
             })
             .to_string(),
         )
         .await?;
+
+        invalidate(&id);
         Ok(())
     }
 
@@ -207,19 +311,24 @@ impl ServiceRequestClient {
         self.rpc(
             ServiceRequestRpc::StartService,
             json!({
-                "_request_id": request_id,
+                "_request_id": &request_id,
                 "_user_id": user_id
             })
             .to_string(),
         )
         .await?;
+
+        invalidate(&request_id);
         Ok(())
     }
 
+    /// Completes the request. When `actual_payment` is `None`, the payment is
+    /// computed by the database as before time logs were introduced.
     pub async fn complete_service<T, U>(
         &self,
         id: T,
         requestor: U,
+        actual_payment: Option<f32>,
     ) -> Result<ServiceRequestData, ClientError>
     where
         T: Serialize,
@@ -229,20 +338,28 @@ impl ServiceRequestClient {
             .rpc(
                 ServiceRequestRpc::CompleteService,
                 json!({
-                    "_request_id": id,
+                    "_request_id": &id,
                     "_user_id": requestor,
+                    "_actual_payment": actual_payment,
                 })
                 .to_string(),
             )
             .await?;
 
-        let values = res.json::<Vec<ServiceRequestData>>().await.map_err(|e| {
-            ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-        })?;
+        // credits moved between the requestor and the provider
+        invalidate(&id);
+        user::invalidate_profiles();
+
+        let values = decode::<Vec<ServiceRequestData>>(res).await?;
 
         Ok(values.into_iter().next().unwrap_or_default())
     }
 
+    /// Number of requests still waiting for a provider.
+    pub async fn count_pending(&self) -> Result<usize, ClientError> {
+        self.count(self.table().select("id").eq("state", "0")).await
+    }
+
     /// Fetch all service requests that is in the pending state.
     pub async fn get_available<T, U>(
         &self,
@@ -255,41 +372,442 @@ impl ServiceRequestClient {
         T: AsRef<str>,
         U: AsRef<str>,
     {
-        let res = self
-            .table()
-            .select("*")
-            .eq("state", "0")
-            .eq(filter_by, filter_value)
-            .range(from, to)
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        let values = res.json::<Vec<ServiceRequestData>>().await.map_err(|e| {
-            ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-        })?;
-
-        Ok(values)
+        self.select(
+            self.table()
+                .select("*")
+                .eq("state", "0")
+                .is("hidden", "false")
+                .gte("date", OffsetDateTime::now_utc().date().to_string())
+                .eq(filter_by, filter_value)
+                .range(from, to),
+        )
+        .await
     }
 
+    /// Cached, see [`invalidate`].
     pub async fn get_summary_for_user<T: Serialize>(
         &self,
         user_id: T,
     ) -> Result<get_summary_for_user::Response, ClientError> {
+        SUMMARIES
+            .get_or_fetch(&user_id, || async {
+                let res = self
+                    .rpc(
+                        ServiceRequestRpc::GetSummaryForUser,
+                        json!({ "_user_id": &user_id }).to_string(),
+                    )
+                    .await?;
+
+                decode::<get_summary_for_user::Response>(res).await
+            })
+            .await
+    }
+
+    pub async fn withdraw_application<T, U>(&self, id: T, provider: U) -> Result<(), ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+    {
+        self.rpc(
+            ServiceRequestRpc::WithdrawApplication,
+            json!({
+                "_request_id": &id,
+                "_provider": provider
+            })
+            .to_string(),
+        )
+        .await?;
+
+        invalidate(&id);
+        Ok(())
+    }
+
+    /// Cancels the request on behalf of `caller`. When the selected provider
+    /// cancels an accepted request, the request goes back to pending instead.
+    pub async fn cancel<T, U, V>(
+        &self,
+        id: T,
+        caller: U,
+        reason: V,
+    ) -> Result<ServiceRequestData, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+    {
+        let res = self
+            .rpc(
+                ServiceRequestRpc::Cancel,
+                json!({
+                    "_request_id": &id,
+                    "_caller": caller,
+                    "_reason": reason,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        invalidate(&id);
+
+        let values = decode::<Vec<ServiceRequestData>>(res).await?;
+
+        Ok(values.into_iter().next().unwrap_or_default())
+    }
+
+    pub async fn open_dispute<T, U, V>(
+        &self,
+        id: T,
+        caller: U,
+        reason: V,
+        claimed_payment: Option<f32>,
+    ) -> Result<Dispute, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+    {
+        let res = self
+            .rpc(
+                ServiceRequestRpc::OpenDispute,
+                json!({
+                    "_request_id": &id,
+                    "_caller": caller,
+                    "_reason": reason,
+                    "_claimed_payment": claimed_payment,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        invalidate(&id);
+
+        let values = decode::<Vec<Dispute>>(res).await?;
+
+        Ok(values.into_iter().next().unwrap_or_default())
+    }
+
+    /// Closes the dispute and sets the request's `actual_payment`. The
+    /// difference from the previous payment is written to the credit ledger
+    /// as a correcting entry in the same transaction.
+    pub async fn resolve_dispute<T, U, V>(
+        &self,
+        dispute_id: T,
+        resolver: U,
+        actual_payment: f32,
+        note: V,
+    ) -> Result<DisputeResolution, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+    {
+        let res = self
+            .rpc(
+                ServiceRequestRpc::ResolveDispute,
+                json!({
+                    "_dispute_id": dispute_id,
+                    "_resolver": resolver,
+                    "_actual_payment": actual_payment,
+                    "_note": note,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        let value = decode::<DisputeResolution>(res).await?;
+
+        // the correcting entry changes the balances of both parties
+        invalidate(&value.request.id);
+        user::invalidate_profiles();
+
+        Ok(value)
+    }
+
+    /// Moves pending requests dated before `before` to the expired state and
+    /// returns them. The `expired` events are recorded by the same call, so a
+    /// request is never expired without one.
+    pub async fn expire_pending<T: Serialize>(
+        &self,
+        before: T,
+    ) -> Result<Vec<ServiceRequestData>, ClientError> {
         let res = self
             .rpc(
-                ServiceRequestRpc::GetSummaryForUser,
-                json!({ "_user_id": user_id }).to_string(),
+                ServiceRequestRpc::ExpirePending,
+                json!({ "_before": before }).to_string(),
             )
+            .await?;
+
+        invalidate_all();
+
+        decode::<Vec<ServiceRequestData>>(res).await
+    }
+
+    /// Rejects the applicants of requests that are expired or have another
+    /// provider selected, and returns the rejected applications. Their
+    /// `application_rejected` events are recorded by the same call.
+    pub async fn reject_unselected_applicants(&self) -> Result<Vec<Application>, ClientError> {
+        let res = self
+            .rpc(ServiceRequestRpc::RejectUnselectedApplicants, "{}")
+            .await?;
+
+        invalidate_all();
+
+        decode::<Vec<Application>>(res).await
+    }
+
+    /// Flags accepted requests dated before `before` that were never started
+    /// as no-shows and returns them. The `no_show` events are recorded by the
+    /// same call.
+    pub async fn flag_no_shows<T: Serialize>(
+        &self,
+        before: T,
+    ) -> Result<Vec<ServiceRequestData>, ClientError> {
+        let res = self
+            .rpc(
+                ServiceRequestRpc::FlagNoShows,
+                json!({ "_before": before }).to_string(),
+            )
+            .await?;
+
+        invalidate_all();
+
+        decode::<Vec<ServiceRequestData>>(res).await
+    }
+
+    pub async fn get_time_log<T: AsRef<str>>(&self, request_id: T) -> Result<TimeLog, ClientError> {
+        let res = self
+            .client
+            .from("service_request_time_entries")
+            .eq("request_id", request_id.as_ref())
+            .order("started_at.asc")
+            .send("service_request_time_entries")
+            .await?;
+
+        let entries = decode::<Vec<TimeEntry>>(res).await?;
+
+        let res = self
+            .client
+            .from("service_request_time_confirmations")
+            .eq("request_id", request_id.as_ref())
+            .send("service_request_time_confirmations")
+            .await?;
+
+        let confirmation = decode::<Vec<TimeLogConfirmation>>(res)
             .await?
-            .json::<get_summary_for_user::Response>()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
+            .into_iter()
+            .next();
+
+        Ok(TimeLog {
+            request_id: request_id.as_ref().to_owned(),
+            logged_minutes: logged_minutes(&entries),
+            entries,
+            confirmation,
+        })
+    }
 
-        Ok(res)
+    pub async fn start_timer<T, U>(
+        &self,
+        request_id: T,
+        provider: U,
+    ) -> Result<TimeEntry, TimeLogError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let log = self.get_time_log(&request_id).await?;
+
+        if log.confirmation.is_some() {
+            return Err(TimeLogError::AlreadyConfirmed);
+        }
+
+        if log.entries.iter().any(|e| e.ended_at.is_none()) {
+            return Err(TimeLogError::TimerRunning);
+        }
+
+        let started_at = now()?;
+
+        self.insert_time_entry(json!({
+            "request_id": request_id.as_ref(),
+            "provider": provider.as_ref(),
+            "started_at": started_at,
+        }))
+        .await
     }
+
+    pub async fn stop_timer<T, U>(
+        &self,
+        request_id: T,
+        provider: U,
+    ) -> Result<TimeEntry, TimeLogError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let log = self.get_time_log(&request_id).await?;
+
+        if log.confirmation.is_some() {
+            return Err(TimeLogError::AlreadyConfirmed);
+        }
+
+        let Some(running) = log.entries.iter().find(|e| e.ended_at.is_none()) else {
+            return Err(TimeLogError::NoTimerRunning);
+        };
+
+        let ended_at = now()?;
+
+        let res = self
+            .client
+            .from("service_request_time_entries")
+            .eq("id", &running.id)
+            .eq("provider", provider.as_ref())
+            .update(json!({ "ended_at": ended_at }).to_string())
+            .send("service_request_time_entries")
+            .await?;
+
+        parse_time_entry(res).await
+    }
+
+    /// Adds an entry for time worked without the timer. The entry must be in
+    /// the past and must not overlap with any logged entry.
+    pub async fn add_time_entry<T, U>(
+        &self,
+        request_id: T,
+        provider: U,
+        started_at: &str,
+        ended_at: &str,
+    ) -> Result<TimeEntry, TimeLogError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let start = parse_timestamp(started_at)?;
+        let end = parse_timestamp(ended_at)?;
+
+        if end <= start {
+            return Err(TimeLogError::InvalidRange);
+        }
+
+        if end > OffsetDateTime::now_utc() {
+            return Err(TimeLogError::InFuture);
+        }
+
+        let log = self.get_time_log(&request_id).await?;
+
+        if log.confirmation.is_some() {
+            return Err(TimeLogError::AlreadyConfirmed);
+        }
+
+        for entry in &log.entries {
+            let entry_start = parse_timestamp(&entry.started_at)?;
+            // a running timer extends up to now
+            let entry_end = match &entry.ended_at {
+                Some(ended_at) => parse_timestamp(ended_at)?,
+                None => OffsetDateTime::now_utc(),
+            };
+
+            if start < entry_end && entry_start < end {
+                return Err(TimeLogError::Overlapping);
+            }
+        }
+
+        self.insert_time_entry(json!({
+            "request_id": request_id.as_ref(),
+            "provider": provider.as_ref(),
+            "started_at": started_at,
+            "ended_at": ended_at,
+        }))
+        .await
+    }
+
+    /// Confirms the logged time on behalf of the requestor. If
+    /// `counter_minutes` is given, it is used as the confirmed total instead
+    /// of the logged one.
+    pub async fn confirm_time_log<T, U>(
+        &self,
+        request_id: T,
+        requestor: U,
+        counter_minutes: Option<u32>,
+    ) -> Result<TimeLog, TimeLogError>
+    where
+        T: AsRef<str>,
+        U: Serialize,
+    {
+        let log = self.get_time_log(&request_id).await?;
+
+        if log.confirmation.is_some() {
+            return Err(TimeLogError::AlreadyConfirmed);
+        }
+
+        if log.entries.is_empty() {
+            return Err(TimeLogError::EmptyLog);
+        }
+
+        if log.entries.iter().any(|e| e.ended_at.is_none()) {
+            return Err(TimeLogError::TimerRunning);
+        }
+
+        let res = self
+            .rpc(
+                ServiceRequestRpc::ConfirmTimeLog,
+                json!({
+                    "_request_id": request_id.as_ref(),
+                    "_requestor": requestor,
+                    "_logged_minutes": log.logged_minutes,
+                    "_confirmed_minutes": counter_minutes.unwrap_or(log.logged_minutes),
+                })
+                .to_string(),
+            )
+            .await?;
+
+        invalidate(request_id.as_ref());
+
+        let confirmation = decode::<Vec<TimeLogConfirmation>>(res)
+            .await?
+            .into_iter()
+            .next();
+
+        Ok(TimeLog {
+            confirmation,
+            ..log
+        })
+    }
+
+    async fn insert_time_entry(&self, entry: serde_json::Value) -> Result<TimeEntry, TimeLogError> {
+        let res = self
+            .client
+            .from("service_request_time_entries")
+            .insert(entry.to_string())
+            .send("service_request_time_entries")
+            .await?;
+
+        parse_time_entry(res).await
+    }
+}
+
+async fn parse_time_entry(res: reqwest::Response) -> Result<TimeEntry, TimeLogError> {
+    let values = decode::<Vec<TimeEntry>>(res).await?;
+
+    Ok(values.into_iter().next().unwrap_or_default())
+}
+
+fn parse_timestamp(value: &str) -> Result<OffsetDateTime, TimeLogError> {
+    OffsetDateTime::parse(value, &Rfc3339).map_err(|_| TimeLogError::InvalidTimestamp(value.into()))
+}
+
+fn now() -> Result<String, TimeLogError> {
+    OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .map_err(|e| TimeLogError::InvalidTimestamp(e.to_string()))
+}
+
+/// Sum of all finished entries, in whole minutes.
+fn logged_minutes(entries: &[TimeEntry]) -> u32 {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let start = OffsetDateTime::parse(&entry.started_at, &Rfc3339).ok()?;
+            let end = OffsetDateTime::parse(entry.ended_at.as_deref()?, &Rfc3339).ok()?;
+            Some((end - start).whole_minutes().max(0) as u32)
+        })
+        .sum()
 }
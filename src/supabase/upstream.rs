@@ -0,0 +1,470 @@
+//! How requests to PostgREST are sent: every call has a timeout, reads are
+//! retried with a jittered backoff when Supabase is briefly unreachable, and
+//! a circuit breaker fails calls fast while it is down.
+//!
+//! Configured with `SUPABASE_TIMEOUT_MS`, `SUPABASE_MAX_RETRIES`,
+//! `SUPABASE_RETRY_BASE_MS`, `SUPABASE_RETRY_MAX_MS`,
+//! `SUPABASE_BREAKER_THRESHOLD` and `SUPABASE_BREAKER_COOLDOWN_SECS`.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use rand::Rng;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use tracing::{info, warn};
+
+use super::{ClientError, InternalErrorKind};
+
+static UPSTREAM: Lazy<Upstream> = Lazy::new(|| Upstream::new(Policy::from_env()));
+
+struct Policy {
+    timeout: Duration,
+    max_retries: u32,
+    retry_base: Duration,
+    retry_max: Duration,
+    breaker_threshold: u32,
+    breaker_cooldown: Duration,
+}
+
+impl Policy {
+    fn from_env() -> Self {
+        fn var(key: &str, default: u64) -> u64 {
+            dotenv::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            timeout: Duration::from_millis(var("SUPABASE_TIMEOUT_MS", 10_000)),
+            max_retries: var("SUPABASE_MAX_RETRIES", 2) as u32,
+            retry_base: Duration::from_millis(var("SUPABASE_RETRY_BASE_MS", 100)),
+            retry_max: Duration::from_millis(var("SUPABASE_RETRY_MAX_MS", 2_000)),
+            breaker_threshold: var("SUPABASE_BREAKER_THRESHOLD", 5).max(1) as u32,
+            breaker_cooldown: Duration::from_secs(var("SUPABASE_BREAKER_COOLDOWN_SECS", 30)),
+        }
+    }
+
+    // "Full jitter": a random delay up to an exponentially growing cap.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let cap = self
+            .retry_base
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.retry_max);
+
+        cap.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // A single call is let through to probe whether Supabase is back. If it
+    // never completes (e.g. it is cancelled), another one is let through
+    // once `until` has passed.
+    HalfOpen { until: Instant },
+}
+
+/// Opens after `threshold` calls in a row failed, and rejects calls until
+/// `cooldown` has passed.
+struct CircuitBreaker {
+    state: Mutex<State>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    fn new(policy: &Policy) -> Self {
+        Self {
+            state: Mutex::new(State::Closed { failures: 0 }),
+            threshold: policy.breaker_threshold,
+            cooldown: policy.breaker_cooldown,
+        }
+    }
+
+    fn acquire(&self) -> Result<(), ClientError> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } | State::HalfOpen { until } if now >= until => {
+                *state = State::HalfOpen {
+                    until: now + self.cooldown,
+                };
+                Ok(())
+            }
+            State::Open { .. } | State::HalfOpen { .. } => Err(ClientError::InternalError(
+                InternalErrorKind::Unavailable(String::from("supabase is unavailable")),
+            )),
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        *state = match (*state, success) {
+            (State::Closed { .. }, true) => State::Closed { failures: 0 },
+            (_, true) => {
+                info!("supabase is reachable again, closing circuit");
+                State::Closed { failures: 0 }
+            }
+            (State::Closed { failures }, false) if failures + 1 < self.threshold => State::Closed {
+                failures: failures + 1,
+            },
+            (State::Open { until }, false) => State::Open { until },
+            (_, false) => {
+                warn!(
+                    "supabase is unavailable, opening circuit for {}s",
+                    self.cooldown.as_secs()
+                );
+                State::Open {
+                    until: Instant::now() + self.cooldown,
+                }
+            }
+        };
+    }
+}
+
+/// Whether `request` only reads data, i.e. is a `GET` or `HEAD`.
+pub(super) fn is_read(request: &RequestBuilder) -> bool {
+    request
+        .try_clone()
+        .and_then(|r| r.build().ok())
+        .is_some_and(|r| matches!(*r.method(), Method::GET | Method::HEAD))
+}
+
+// Responses that mean Supabase, or the gateway in front of it, is down
+// rather than that the query failed.
+fn is_unavailable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Sends `request` to Supabase, see [`Upstream::send`].
+pub(super) async fn send(request: RequestBuilder, read: bool) -> Result<Response, ClientError> {
+    UPSTREAM.send(request, read).await
+}
+
+/// A policy and the circuit breaker that goes with it. The clients share one,
+/// configured from the environment.
+struct Upstream {
+    policy: Policy,
+    breaker: CircuitBreaker,
+}
+
+impl Upstream {
+    fn new(policy: Policy) -> Self {
+        Self {
+            breaker: CircuitBreaker::new(&policy),
+            policy,
+        }
+    }
+
+    /// Sends `request` with the timeout, retries and circuit breaker of the
+    /// policy. Only reads are retried, except when the connection couldn't be
+    /// made at all, in which case nothing was sent.
+    async fn send(&self, request: RequestBuilder, read: bool) -> Result<Response, ClientError> {
+        let policy = &self.policy;
+        let mut next = Some(request);
+        let mut attempt = 0;
+
+        loop {
+            self.breaker.acquire()?;
+
+            let current = next.take().expect("request to send");
+            next = current.try_clone();
+
+            let res = current.timeout(policy.timeout).send().await;
+
+            let failed = match &res {
+                Ok(res) => is_unavailable(res.status()),
+                Err(e) => e.is_timeout() || e.is_connect(),
+            };
+
+            self.breaker.record(!failed);
+
+            let retry = failed
+                && next.is_some()
+                && attempt < policy.max_retries
+                && match &res {
+                    Ok(_) => read,
+                    Err(e) => read || e.is_connect(),
+                };
+
+            if !retry {
+                return res.map_err(|e| {
+                    let kind = if e.is_timeout() || e.is_connect() {
+                        InternalErrorKind::Unavailable(e.to_string())
+                    } else {
+                        InternalErrorKind::RequestError(e.to_string())
+                    };
+
+                    ClientError::InternalError(kind)
+                });
+            }
+
+            attempt += 1;
+
+            let delay = policy.backoff(attempt);
+            match &res {
+                Ok(res) => warn!(status = %res.status(), ?delay, attempt, "retrying supabase call"),
+                Err(e) => warn!(?delay, attempt, "retrying supabase call error={e}"),
+            }
+
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{http::StatusCode as HttpStatus, Router, Server};
+
+    use super::*;
+
+    /// A local HTTP server answering with `replies` in order, then repeating
+    /// the last one. Each reply is a status and how long to wait before
+    /// sending it.
+    struct MockServer {
+        addr: SocketAddr,
+        client: reqwest::Client,
+        replies: Arc<Mutex<Vec<(u16, Duration)>>>,
+        hits: Arc<AtomicUsize>,
+    }
+
+    impl MockServer {
+        fn start(replies: &[(u16, Duration)]) -> Self {
+            let replies = Arc::new(Mutex::new(replies.to_vec()));
+            let hits = Arc::new(AtomicUsize::new(0));
+
+            let router = Router::new().fallback({
+                let replies = replies.clone();
+                let hits = hits.clone();
+
+                move || async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+
+                    let (status, delay) = {
+                        let mut replies = replies.lock().unwrap();
+                        match replies.len() {
+                            0 => (200, Duration::ZERO),
+                            1 => replies[0],
+                            _ => replies.remove(0),
+                        }
+                    };
+
+                    tokio::time::sleep(delay).await;
+                    HttpStatus::from_u16(status).unwrap()
+                }
+            });
+
+            let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+                .serve(router.into_make_service());
+            let addr = server.local_addr();
+            tokio::spawn(server);
+
+            Self {
+                addr,
+                client: reqwest::Client::new(),
+                replies,
+                hits,
+            }
+        }
+
+        fn reply_with(&self, replies: &[(u16, Duration)]) {
+            *self.replies.lock().unwrap() = replies.to_vec();
+        }
+
+        fn hits(&self) -> usize {
+            self.hits.load(Ordering::SeqCst)
+        }
+
+        fn get(&self) -> RequestBuilder {
+            self.client
+                .get(format!("http://{}/rest/v1/users", self.addr))
+        }
+
+        fn post(&self) -> RequestBuilder {
+            self.client
+                .post(format!("http://{}/rest/v1/users", self.addr))
+                .body("{}")
+        }
+    }
+
+    fn policy() -> Policy {
+        Policy {
+            timeout: Duration::from_millis(100),
+            max_retries: 2,
+            retry_base: Duration::from_millis(1),
+            retry_max: Duration::from_millis(5),
+            breaker_threshold: 100,
+            breaker_cooldown: Duration::from_millis(200),
+        }
+    }
+
+    fn is_unavailable_error(res: &Result<Response, ClientError>) -> bool {
+        matches!(
+            res,
+            Err(ClientError::InternalError(InternalErrorKind::Unavailable(
+                _
+            )))
+        )
+    }
+
+    const NOW: Duration = Duration::ZERO;
+    const SLOW: Duration = Duration::from_millis(500);
+
+    #[tokio::test]
+    async fn retries_reads_when_supabase_is_unavailable() {
+        for status in [502, 503, 504] {
+            let server = MockServer::start(&[(status, NOW), (status, NOW), (200, NOW)]);
+            let upstream = Upstream::new(policy());
+
+            let res = upstream.send(server.get(), true).await.unwrap();
+
+            assert_eq!(res.status(), StatusCode::OK, "status {status}");
+            assert_eq!(server.hits(), 3, "status {status}");
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_reads_after_max_retries() {
+        let server = MockServer::start(&[(503, NOW)]);
+        let upstream = Upstream::new(policy());
+
+        let res = upstream.send(server.get(), true).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_reads_that_time_out() {
+        let server = MockServer::start(&[(200, SLOW), (200, NOW)]);
+        let upstream = Upstream::new(policy());
+
+        let res = upstream.send(server.get(), true).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(server.hits(), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_errors() {
+        let server = MockServer::start(&[(500, NOW), (200, NOW)]);
+        let upstream = Upstream::new(policy());
+
+        let res = upstream.send(server.get(), true).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_writes_once_sent() {
+        let server = MockServer::start(&[(503, NOW), (200, NOW)]);
+        let upstream = Upstream::new(policy());
+
+        let res = upstream.send(server.post(), false).await.unwrap();
+
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.hits(), 1);
+
+        let server = MockServer::start(&[(200, SLOW), (200, NOW)]);
+
+        let res = upstream.send(server.post(), false).await;
+
+        assert!(is_unavailable_error(&res));
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn breaker_opens_after_threshold_and_closes_after_probe() {
+        let server = MockServer::start(&[(503, NOW)]);
+        let upstream = Upstream::new(Policy {
+            max_retries: 0,
+            breaker_threshold: 3,
+            ..policy()
+        });
+
+        for _ in 0..3 {
+            let res = upstream.send(server.get(), true).await.unwrap();
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        // open : calls fail without reaching the server
+        let res = upstream.send(server.get(), true).await;
+        assert!(is_unavailable_error(&res));
+        assert_eq!(server.hits(), 3);
+
+        tokio::time::sleep(upstream.policy.breaker_cooldown).await;
+        server.reply_with(&[(200, Duration::from_millis(50))]);
+
+        // half-open : a single probe is let through, other calls still fail
+        let (probe, other) = tokio::join!(upstream.send(server.get(), true), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            upstream.send(server.get(), true).await
+        });
+
+        assert_eq!(probe.unwrap().status(), StatusCode::OK);
+        assert!(is_unavailable_error(&other));
+        assert_eq!(server.hits(), 4);
+
+        // the probe succeeded, so the circuit is closed
+        server.reply_with(&[(200, NOW)]);
+
+        let res = upstream.send(server.get(), true).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(server.hits(), 5);
+
+        // closed : failures are counted from zero again
+        server.reply_with(&[(503, NOW)]);
+
+        for _ in 0..2 {
+            upstream.send(server.get(), true).await.unwrap();
+        }
+
+        let res = upstream.send(server.get(), true).await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.hits(), 8);
+    }
+
+    #[tokio::test]
+    async fn breaker_reopens_when_probe_fails() {
+        let server = MockServer::start(&[(503, NOW)]);
+        let upstream = Upstream::new(Policy {
+            max_retries: 0,
+            breaker_threshold: 1,
+            ..policy()
+        });
+
+        upstream.send(server.get(), true).await.unwrap();
+        assert!(is_unavailable_error(
+            &upstream.send(server.get(), true).await
+        ));
+
+        tokio::time::sleep(upstream.policy.breaker_cooldown).await;
+
+        // the probe fails, so the circuit opens again for a full cooldown
+        upstream.send(server.get(), true).await.unwrap();
+        assert!(is_unavailable_error(
+            &upstream.send(server.get(), true).await
+        ));
+        assert_eq!(server.hits(), 2);
+    }
+}
@@ -1,11 +1,93 @@
 use crate::proto::user::{
-    get_credit_balance, CreditTransaction, NewUserProfile, ProfileSummary, UserProfile,
+    get_credit_balance, transfer_credits::request::Recipient, CreditTransaction, NewUserProfile,
+    ProfileSummary, TransactionDirection, TransactionFilter, UserProfile,
 };
-use crate::supabase::{self, rpc::UserRpc, ClientError, InternalErrorKind, PostgrestError, Schema};
+use crate::supabase::{self, cache::Cache, decode, rpc::UserRpc, ClientError, Execute, Schema};
 
+use once_cell::sync::Lazy;
 use postgrest::Builder;
-use serde::Serialize;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const TABLE: &str = "profiles";
+
+/// The error code `users_placecredithold` raises when the available balance
+/// doesn't cover the estimated payment.
+pub const INSUFFICIENT_CREDITS: &str = "TB001";
+
+static PROFILES: Lazy<Cache<ProfileSummary>> = Lazy::new(|| Cache::new("users.get_profile"));
+
+/// Drops the cached profile of `user_id`, after a change to the profile, its
+/// credits or its ratings.
+pub(super) fn invalidate_profile<T: Serialize>(user_id: T) {
+    PROFILES.invalidate(user_id);
+}
+
+/// Drops every cached profile, for changes that affect users that aren't
+/// known here, e.g. both parties of a completed request.
+pub(super) fn invalidate_profiles() {
+    PROFILES.clear();
+}
+
+/// Position in the transaction history, which is ordered from newest to
+/// oldest. It is handed to clients as an opaque string.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryCursor {
+    pub created_at: String,
+    pub id: String,
+}
+
+impl HistoryCursor {
+    pub fn after(transaction: &CreditTransaction) -> Self {
+        Self {
+            created_at: transaction.created_at.clone(),
+            id: transaction.id.clone(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let value = serde_json::to_vec(self).unwrap_or_default();
+        base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let value = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&value).ok()
+    }
+}
+
+/// Returned by `users_transfercredits`. If a transfer with the same
+/// idempotency key was already made by the sender, `transaction` is that
+/// transfer and `replayed` is set.
+#[derive(Debug, Default, Deserialize)]
+pub struct Transfer {
+    pub transaction: CreditTransaction,
+    pub replayed: bool,
+}
+
+/// Credits of a requestor set aside for a service request, from the moment
+/// a provider is selected until the request is completed or cancelled. For a
+/// request made on behalf of an organisation, the credits are the
+/// organisation's.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CreditHold {
+    pub request_id: String,
+    pub user_id: String,
+    #[serde(default)]
+    pub organisation_id: Option<String>,
+    pub amount: f32,
+}
+
+/// Why a credit hold is released. Each is recorded as its own entry type in
+/// the transaction history.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldRelease {
+    /// The request was completed and the actual payment has been made.
+    Settled,
+    /// The request did not go ahead and the credits go back to the requestor.
+    Refunded,
+}
 
 #[derive(Default)]
 pub struct UserClient {
@@ -15,9 +97,10 @@ pub struct UserClient {
 #[tonic::async_trait]
 impl Schema for UserClient {
     type Method = UserRpc;
+    const TABLE: &'static str = TABLE;
 
     fn table(&self) -> Builder {
-        self.client.from("profiles")
+        self.client.from(TABLE)
     }
 
     async fn rpc<T: Into<String> + std::marker::Send>(
@@ -41,63 +124,43 @@ impl UserClient {
         T: AsRef<str>,
         U: AsRef<str>,
     {
-        let res = self
-            .table()
-            .eq(column, filter)
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        let values = res.json::<Vec<UserProfile>>().await.map_err(|e| {
-            ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-        })?;
-
-        Ok(values)
+        self.select(self.table().eq(column, filter)).await
     }
 
-    pub async fn update<T, U>(&self, user_id: T, body: U) -> Result<UserProfile, ClientError>
+    /// Updates the profile unless one of the `unchanged` columns was changed
+    /// since it was read, in which case `None` is returned.
+    pub async fn update<T, U>(
+        &self,
+        user_id: T,
+        body: U,
+        unchanged: &[(String, Value)],
+    ) -> Result<Option<UserProfile>, ClientError>
     where
         T: AsRef<str>,
         U: Into<String>,
     {
-        let res = self
-            .table()
-            .eq("user_id", user_id)
-            .update(body)
-            .execute()
-            .await
-            .map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::RequestError(e.to_string()))
-            })?;
-
-        if res.status().is_success() {
-            let values = res.json::<Vec<UserProfile>>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Ok(values.into_iter().next().unwrap_or_default())
-        } else {
-            let err = res.json::<PostgrestError>().await.map_err(|e| {
-                ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-            })?;
-
-            Err(ClientError::SupabaseError(err))
-        }
+        let query = supabase::unchanged(self.table().eq("user_id", user_id.as_ref()), unchanged);
+        let values: Vec<UserProfile> = Schema::update(self, query, body.into()).await?;
+
+        invalidate_profile(user_id.as_ref());
+
+        Ok(values.into_iter().next())
     }
 
+    /// Cached, see [`invalidate_profile`].
     pub async fn get_profile(&self, user_id: &str) -> Result<ProfileSummary, ClientError> {
-        let res = self
-            .rpc(
-                UserRpc::GetProfile,
-                json!({ "_user_id": user_id }).to_string(),
-            )
-            .await?;
+        PROFILES
+            .get_or_fetch(user_id, || async {
+                let res = self
+                    .rpc(
+                        UserRpc::GetProfile,
+                        json!({ "_user_id": user_id }).to_string(),
+                    )
+                    .await?;
 
-        res.json::<ProfileSummary>()
+                decode::<ProfileSummary>(res).await
+            })
             .await
-            .map_err(|e| ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string())))
     }
 
     pub(crate) async fn create_new_profile<T>(
@@ -119,9 +182,7 @@ impl UserClient {
             )
             .await?;
 
-        res.json::<Vec<UserProfile>>()
-            .await
-            .map_err(|e| ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string())))
+        decode::<Vec<UserProfile>>(res).await
     }
 
     pub async fn check_if_email_exist<T>(&self, id: T) -> Result<bool, ClientError>
@@ -135,38 +196,208 @@ impl UserClient {
             )
             .await?;
 
-        let value = res.json::<bool>().await.map_err(|e| {
-            ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string()))
-        })?;
-
-        Ok(value)
+        decode::<bool>(res).await
     }
 
+    /// The balance is split into credits that are available to spend and
+    /// credits on hold for accepted service requests.
     pub async fn get_credit_balance<T: Serialize>(
         &self,
         user_id: T,
     ) -> Result<get_credit_balance::Response, ClientError> {
-        self.rpc(
-            UserRpc::GetCreditBalance,
-            json!({ "_user_id": user_id }).to_string(),
-        )
-        .await?
-        .json::<get_credit_balance::Response>()
-        .await
-        .map_err(|e| ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string())))
+        let res = self
+            .rpc(
+                UserRpc::GetCreditBalance,
+                json!({ "_user_id": user_id }).to_string(),
+            )
+            .await?;
+
+        decode::<get_credit_balance::Response>(res).await
     }
 
+    /// Fetches up to `limit` transactions of the user matching `filter`,
+    /// newest first, starting after `after` if given.
     pub async fn get_transaction_history<T: Serialize>(
         &self,
         user_id: T,
+        filter: &TransactionFilter,
+        after: Option<&HistoryCursor>,
+        limit: usize,
     ) -> Result<Vec<CreditTransaction>, ClientError> {
+        let direction = match TransactionDirection::from_i32(filter.direction) {
+            Some(TransactionDirection::Earned) => Some("earned"),
+            Some(TransactionDirection::Spent) => Some("spent"),
+            _ => None,
+        };
+
+        let res = self
+            .rpc(
+                UserRpc::GetTransactionHistory,
+                json!({
+                    "_user_id": user_id,
+                    "_from": filter.from,
+                    "_to": filter.to,
+                    "_direction": direction,
+                    "_counterparty": filter.counterparty,
+                    "_after_created_at": after.map(|c| &c.created_at),
+                    "_after_id": after.map(|c| &c.id),
+                    "_limit": limit,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        decode::<Vec<CreditTransaction>>(res).await
+    }
+
+    /// Places the estimated payment of the request on hold from the balance
+    /// of `organisation_id` when the request is made on behalf of one, of
+    /// `user_id` otherwise. Fails with [`INSUFFICIENT_CREDITS`] if the
+    /// available balance is not sufficient.
+    pub async fn place_credit_hold<T, U>(
+        &self,
+        request_id: T,
+        user_id: U,
+        organisation_id: Option<&str>,
+    ) -> Result<CreditHold, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+    {
+        let res = self
+            .rpc(
+                UserRpc::PlaceCreditHold,
+                json!({
+                    "_request_id": request_id,
+                    "_user_id": &user_id,
+                    "_organisation_id": organisation_id.filter(|id| !id.is_empty()),
+                })
+                .to_string(),
+            )
+            .await?;
+
+        invalidate_profile(&user_id);
+
+        let values = decode::<Vec<CreditHold>>(res).await?;
+
+        Ok(values.into_iter().next().unwrap_or_default())
+    }
+
+    /// Releases the hold of the request, to or from the balance it was placed
+    /// on (see [`UserClient::place_credit_hold`]). A hold that was already
+    /// released is left as it is, so this is safe to retry.
+    pub async fn release_credit_hold<T: Serialize>(
+        &self,
+        request_id: T,
+        release: HoldRelease,
+    ) -> Result<(), ClientError> {
         self.rpc(
-            UserRpc::GetTransactionHistory,
-            json!({ "_user_id": user_id }).to_string(),
+            UserRpc::ReleaseCreditHold,
+            json!({
+                "_request_id": request_id,
+                "_release": release,
+            })
+            .to_string(),
         )
-        .await?
-        .json::<Vec<CreditTransaction>>()
-        .await
-        .map_err(|e| ClientError::InternalError(InternalErrorKind::ParsingError(e.to_string())))
+        .await?;
+
+        // the hold only knows its request, not whose credits it holds
+        invalidate_profiles();
+        Ok(())
+    }
+
+    /// Releases the holds that should have been released already, and returns
+    /// them: those of completed requests are settled, and those of requests
+    /// that didn't go ahead are refunded, like [`UserClient::release_credit_hold`]
+    /// does. Holds of pending requests are only
+    /// released once placed before `placed_before`, since a provider may be
+    /// being selected.
+    pub async fn release_stale_holds<T: Serialize>(
+        &self,
+        placed_before: T,
+    ) -> Result<Vec<CreditHold>, ClientError> {
+        let res = self
+            .rpc(
+                UserRpc::ReleaseStaleHolds,
+                json!({ "_placed_before": placed_before }).to_string(),
+            )
+            .await?;
+
+        let values = decode::<Vec<CreditHold>>(res).await?;
+
+        if !values.is_empty() {
+            invalidate_profiles();
+        }
+
+        Ok(values)
+    }
+
+    /// The transfer previously made by `sender` with `idempotency_key`, if any.
+    pub async fn get_transfer_by_key<T, U>(
+        &self,
+        sender: T,
+        idempotency_key: U,
+    ) -> Result<Option<CreditTransaction>, ClientError>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        let res = self
+            .client
+            .from("credit_transactions")
+            .eq("sender", sender)
+            .eq("idempotency_key", idempotency_key)
+            .send("credit_transactions")
+            .await?;
+
+        let values = decode::<Vec<CreditTransaction>>(res).await?;
+
+        Ok(values.into_iter().next())
+    }
+
+    /// Moves `amount` credits from `sender` to a user or a pool. The
+    /// available balance of the sender is checked again in the transaction,
+    /// which fails with [`INSUFFICIENT_CREDITS`] if it fell short.
+    pub async fn transfer_credits<T, U, V>(
+        &self,
+        sender: T,
+        recipient: &Recipient,
+        amount: f32,
+        memo: U,
+        idempotency_key: V,
+    ) -> Result<Transfer, ClientError>
+    where
+        T: Serialize,
+        U: Serialize,
+        V: Serialize,
+    {
+        let (recipient_user, recipient_pool) = match recipient {
+            Recipient::UserId(id) => (Some(id), None),
+            Recipient::PoolId(id) => (None, Some(id)),
+        };
+
+        let res = self
+            .rpc(
+                UserRpc::TransferCredits,
+                json!({
+                    "_sender": &sender,
+                    "_recipient_user": recipient_user,
+                    "_recipient_pool": recipient_pool,
+                    "_amount": amount,
+                    "_memo": memo,
+                    "_idempotency_key": idempotency_key,
+                })
+                .to_string(),
+            )
+            .await?;
+
+        let transfer = decode::<Transfer>(res).await?;
+
+        invalidate_profile(&sender);
+        if let Some(id) = recipient_user {
+            invalidate_profile(id);
+        }
+
+        Ok(transfer)
     }
 }
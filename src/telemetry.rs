@@ -0,0 +1,52 @@
+//! OpenTelemetry trace export over OTLP, enabled by setting
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` (e.g. `http://localhost:4317`).
+
+use opentelemetry::{
+    global,
+    sdk::{propagation::TraceContextPropagator, trace, Resource},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+const DEFAULT_SERVICE_NAME: &str = "timebank-server";
+
+/// The layer exporting spans to the collector, or `None` if no endpoint is
+/// configured. The W3C trace context propagator is installed either way, so
+/// that incoming trace ids are kept in the logs.
+pub fn layer<S>() -> Option<OpenTelemetryLayer<S, trace::Tracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let endpoint = dotenv::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+
+    let service_name =
+        dotenv::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| DEFAULT_SERVICE_NAME.to_owned());
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                service_name,
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)
+        .expect("UNABLE TO START OTLP EXPORTER");
+
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Flushes the spans that haven't been exported yet.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
@@ -0,0 +1,225 @@
+//! Optional TLS termination for the gRPC server and the REST gateway, enabled
+//! by setting `TLS_CERT_PATH` and `TLS_KEY_PATH`. Setting `TLS_CLIENT_CA_PATH`
+//! also verifies client certificates against that CA (mutual TLS), either for
+//! every client or, with `TLS_CLIENT_AUTH=optional`, only for the clients that
+//! send one. The files are reloaded when they change, so certificates
+//! can be rotated without a restart.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::{
+    rustls::{
+        server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+        Certificate, PrivateKey, RootCertStore, ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, info, warn};
+
+const DEFAULT_RELOAD_INTERVAL_SECS: u64 = 30;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca: Option<PathBuf>,
+    client_auth_optional: bool,
+}
+
+impl TlsConfig {
+    /// `None` when TLS isn't configured.
+    pub fn from_env() -> Option<Self> {
+        let cert = dotenv::var("TLS_CERT_PATH").ok()?;
+        let key = dotenv::var("TLS_KEY_PATH").expect("MISSING TLS KEY PATH");
+
+        Some(Self {
+            cert: cert.into(),
+            key: key.into(),
+            client_ca: dotenv::var("TLS_CLIENT_CA_PATH").ok().map(PathBuf::from),
+            client_auth_optional: dotenv::var("TLS_CLIENT_AUTH").as_deref() == Ok("optional"),
+        })
+    }
+
+    fn load(&self) -> io::Result<ServerConfig> {
+        let certs = read_certs(&self.cert)?;
+        let key = read_key(&self.key)?;
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+
+        let builder = match &self.client_ca {
+            Some(path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(path)? {
+                    roots.add(&cert).map_err(invalid_data)?;
+                }
+
+                let verifier = if self.client_auth_optional {
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+                } else {
+                    AllowAnyAuthenticatedClient::new(roots)
+                };
+
+                builder.with_client_cert_verifier(verifier)
+            }
+
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder.with_single_cert(certs, key).map_err(invalid_data)?;
+        // HTTP/1.1 is needed by gRPC-Web clients
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(config)
+    }
+
+    // The modification times of the files, to tell when they need reloading.
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| path.metadata().and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+/// Accepts connections on `addr` and completes their TLS handshake, to be
+/// passed to `Server::serve_with_incoming` or `hyper::Server::builder`.
+/// Handshakes run in their own task, so a slow client doesn't hold up the
+/// others.
+pub async fn incoming(
+    addr: SocketAddr,
+    config: TlsConfig,
+) -> io::Result<ReceiverStream<io::Result<TlsStream<TcpStream>>>> {
+    let current = Arc::new(RwLock::new(Arc::new(config.load()?)));
+    let listener = TcpListener::bind(addr).await?;
+
+    watch(config, current.clone());
+
+    let (tx, rx) = mpsc::channel(64);
+
+    tokio::spawn(async move {
+        let mut backoff = MIN_ACCEPT_BACKOFF;
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                // e.g. running out of file descriptors, which fails every
+                // accept until some connections are closed
+                Err(e) => {
+                    warn!("unable to accept connection, retrying in {backoff:?} error={e}");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+
+            backoff = MIN_ACCEPT_BACKOFF;
+
+            let _ = stream.set_nodelay(true);
+
+            let acceptor = TlsAcceptor::from(current.read().unwrap().clone());
+            let tx = tx.clone();
+
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => debug!(%peer, "TLS handshake failed error={e}"),
+                    Err(_) => debug!(%peer, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+
+    Ok(ReceiverStream::new(rx))
+}
+
+// Reloads the configuration every TLS_RELOAD_INTERVAL_SECS when one of its
+// files changed. New connections use the reloaded configuration, and a
+// configuration that fails to load is skipped until its files change again.
+fn watch(config: TlsConfig, current: Arc<RwLock<Arc<ServerConfig>>>) {
+    // an interval of 0 would make `tokio::time::interval` panic
+    let interval = dotenv::var("TLS_RELOAD_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|s| *s > 0)
+        .unwrap_or(DEFAULT_RELOAD_INTERVAL_SECS);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        let mut modified = config.modified();
+
+        loop {
+            ticker.tick().await;
+
+            let latest = config.modified();
+            if latest == modified {
+                continue;
+            }
+
+            modified = latest;
+
+            match config.load() {
+                Ok(loaded) => {
+                    *current.write().unwrap() = Arc::new(loaded);
+                    info!("reloaded TLS configuration");
+                }
+                Err(e) => warn!("unable to reload TLS configuration error={e}"),
+            }
+        }
+    });
+}
+
+fn read_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+
+    if certs.is_empty() {
+        return Err(invalid_data(format!(
+            "no certificate found in {}",
+            path.display()
+        )));
+    }
+
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+
+    Err(invalid_data(format!(
+        "no private key found in {}",
+        path.display()
+    )))
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}